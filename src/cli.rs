@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+/// Every public IP was published (or was already up to date)
+pub const EXIT_OK: u8 = 0;
+/// At least one account could not be updated
+pub const EXIT_UPDATE_FAILED: u8 = 1;
+/// The config file is missing or invalid, or the log file cannot be opened
+pub const EXIT_CONFIG: u8 = 2;
+/// The router could not be reached or returned an unusable answer
+pub const EXIT_ROUTER: u8 = 3;
+
+#[derive(Parser)]
+#[command(name = "dynhost-client", version, about = "Publishes the public IP to OVH DynHost and other dyndns2 providers")]
+pub struct Cli
+{
+    /// Config file to use instead of $XDG_CONFIG_HOME/dynhost/config.toml or /etc/dynhost/config.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command
+{
+    /// Watch the public IP and keep the accounts up to date (default)
+    Run,
+    /// Detect the public IP, update the accounts once and exit
+    Once,
+    /// Print the router WAN state and the IP each account currently resolves to
+    Status,
+    /// List the internet outages recorded by the daemon, with their durations
+    Outages,
+    /// Validate the config, the router credentials and the account credentials without changing anything
+    CheckConfig,
+    /// Dump the router WAN status as JSON
+    RouterInfo,
+    /// Bring the router entries enabled in [reconcile] in line with the config once
+    Reconcile
+    {
+        /// Only print the changes that would be made
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List the LAN devices known by the router
+    Devices
+    {
+        #[arg(long, value_enum, default_value_t = DeviceFormat::Table)]
+        format: DeviceFormat,
+        /// Only list the connected devices
+        #[arg(long)]
+        active: bool,
+    },
+    /// Talk to the FunBox API directly
+    #[command(subcommand)]
+    Funbox(FunboxCommand),
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+pub enum DeviceFormat
+{
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Subcommand)]
+pub enum FunboxCommand
+{
+    /// Call any /ws method and print the answer as JSON, e.g. `funbox call NMC getWANStatus`
+    Call
+    {
+        /// Service, e.g. `NMC` or `NeMo.Intf.data`
+        service: String,
+        /// Method of the service, e.g. `getWANStatus`
+        method: String,
+        /// Named arguments as a JSON object, e.g. '{"mibs":"dhcp"}'
+        #[arg(default_value = "{}")]
+        params: String,
+    },
+}
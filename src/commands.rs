@@ -0,0 +1,505 @@
+use std::net::{IpAddr, ToSocketAddrs};
+use std::process::ExitCode;
+
+use chrono::Local;
+
+use crate::cli::{DeviceFormat, EXIT_CONFIG, EXIT_OK, EXIT_ROUTER, EXIT_UPDATE_FAILED};
+use crate::config::{Config, RouterConfig};
+use crate::funbox::{discovery, SahError, Session};
+use crate::funbox::devices::Device;
+use crate::policy;
+use crate::provider;
+use crate::reconcile;
+use crate::source;
+use crate::state::{PollState, State};
+use crate::util::{classify, format_duration};
+
+fn connect_router(router: &RouterConfig) -> Result<Session, SahError>
+{
+    Session::connect(&router.address, &router.username, &router.password, router.session_cache())
+}
+
+/// Resolves `address = "auto"` in `[router]` from the daemon cache or by discovery,
+/// without writing the state file which usually belongs to the daemon user; fails with
+/// the exit code when no router was found
+pub fn resolve_router(config: &mut Config) -> Result<(), ExitCode>
+{
+    let Some(router) = config.router.as_mut() else
+    {
+        return Ok(());
+    };
+
+    let mut cache = State::load(&config.daemon.state_file).ok().and_then(|state| state.router);
+
+    match discovery::resolve(router, &mut cache)
+    {
+        Ok(Some(note)) => println!("{}", note),
+        Ok(None) => {}
+        Err(e) => {
+            println!("{}", e);
+            return Err(ExitCode::from(EXIT_ROUTER));
+        }
+    }
+
+    Ok(())
+}
+
+fn print_hint(e: &SahError)
+{
+    if let Some(hint) = e.hint()
+    {
+        println!("  hint: {}", hint);
+    }
+}
+
+/// The address with its class, e.g. `100.64.1.2 (carrier-grade NAT address in 100.64.0.0/10)`
+fn describe_ip(ip: Option<IpAddr>) -> String
+{
+    match ip
+    {
+        Some(ip) => format!("{} ({})", ip, classify(&ip).description()),
+        None => "none".to_string(),
+    }
+}
+
+/// Addresses the domain currently resolves to, i.e. what was last published
+fn resolve(domain: &str) -> Result<Vec<IpAddr>, String>
+{
+    let mut addrs: Vec<IpAddr> = (domain, 0).to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .map(|addr| addr.ip())
+        .collect();
+
+    addrs.sort();
+    addrs.dedup();
+
+    Ok(addrs)
+}
+
+pub fn status(config: &Config) -> ExitCode
+{
+    let mut code = EXIT_OK;
+
+    let mut public_ip = None;
+
+    if let Some(router) = &config.router
+    {
+        match connect_router(router).and_then(|mut sess| sess.get_wan_status())
+        {
+            Ok(status) => {
+                let wan = status.data;
+                println!("Router {}", router.address);
+                println!("  WAN state:        {}", wan.WanState);
+                println!("  Link:             {} ({})", wan.LinkType, wan.LinkState);
+                if let Some(gpon) = &wan.GponState
+                {
+                    println!("  GPON state:       {}", gpon);
+                }
+                println!("  Connection state: {}", wan.ConnectionState);
+                println!("  IPv4 address:     {}", describe_ip(wan.IPAddress));
+                println!("  IPv6 address:     {}", describe_ip(wan.IPv6Address));
+            }
+            Err(e) => {
+                println!("Router {}: could not get WAN status: {}", router.address, e);
+                print_hint(&e);
+                code = EXIT_ROUTER;
+            }
+        }
+
+        println!();
+    }
+
+    match source::create(config).and_then(|mut src| src.detect().map_err(|e| format!("{}: {}", src.name(), e)))
+    {
+        Ok(mut detection) => {
+            let addrs = detection.addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ");
+            println!("Public IP from {}: {}", detection.source, addrs);
+            for note in policy::apply(&mut detection, &config.policy)
+            {
+                println!("  {}", note);
+            }
+            public_ip = Some(detection);
+        }
+        Err(e) => {
+            println!("Could not get public IP: {}", e);
+            code = EXIT_ROUTER;
+        }
+    }
+
+    let state = match State::load(&config.daemon.state_file)
+    {
+        Ok(state) => state,
+        Err(e) => {
+            println!("{}", e);
+            State::default()
+        }
+    };
+
+    println!();
+    match &PollState::load(&config.daemon.state_file)
+    {
+        Some(poll) if poll.mode == "events" => println!("Daemon waiting for router events, checking anyway at {}",
+            poll.next_poll.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")),
+        Some(poll) => println!("Daemon polling every {}s, next poll at {}", poll.interval_secs,
+            poll.next_poll.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")),
+        None => println!("Daemon has no poll scheduled, it is probably not running"),
+    }
+
+    match state.current_outage()
+    {
+        Some(outage) => println!("Internet connection down since {} ({})",
+            outage.started_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), format_duration(outage.duration())),
+        None => if let Some(outage) = state.outages.last()
+        {
+            println!("Last outage: {} for {}, {} recorded (see the outages command)",
+                outage.started_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), format_duration(outage.duration()),
+                state.outages.len());
+        },
+    }
+
+    println!();
+    println!("Accounts");
+
+    for account in &config.accounts
+    {
+        let addrs = match resolve(&account.domain)
+        {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                println!("  {}: could not resolve: {}", account.domain, e);
+                continue;
+            }
+        };
+
+        for record in &account.records
+        {
+            let published = addrs.iter().filter(|a| record.matches(a)).map(|a| a.to_string()).collect::<Vec<_>>();
+            let up_to_date = match public_ip.as_ref().and_then(|d| d.address_for(*record))
+            {
+                Some(ip) if addrs.contains(&ip) => "up to date",
+                Some(_) => "outdated",
+                None => "unknown",
+            };
+
+            println!("  {} {}: {} ({})", account.domain, record,
+                if published.is_empty() { "none".to_string() } else { published.join(", ") }, up_to_date);
+
+            if let Some(rec) = state.record(&account.domain, *record)
+            {
+                if let (Some(ip), Some(at)) = (rec.last_ip, rec.updated_at)
+                {
+                    println!("    last published {} at {}", ip, at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"));
+                }
+                if let Some(response) = &rec.last_response
+                {
+                    println!("    last response: {}{}", response,
+                        if rec.failures > 0 { format!(" ({} failures in a row)", rec.failures) } else { String::new() });
+                }
+            }
+        }
+    }
+
+    ExitCode::from(code)
+}
+
+pub fn outages(config: &Config) -> ExitCode
+{
+    let state = match State::load(&config.daemon.state_file)
+    {
+        Ok(state) => state,
+        Err(e) => {
+            println!("{}", e);
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
+
+    if state.outages.is_empty()
+    {
+        println!("No outage recorded");
+        return ExitCode::from(EXIT_OK);
+    }
+
+    let mut total = chrono::Duration::zero();
+
+    for outage in &state.outages
+    {
+        let end = match outage.ended_at
+        {
+            Some(end) => end.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => "ongoing".to_string(),
+        };
+
+        println!("{}  {:<19}  {}", outage.started_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"), end,
+            format_duration(outage.duration()));
+        total += outage.duration();
+    }
+
+    println!();
+    println!("{} outages, {} in total", state.outages.len(), format_duration(total));
+
+    ExitCode::from(EXIT_OK)
+}
+
+pub fn check_config(config: &Config) -> ExitCode
+{
+    let mut code = EXIT_OK;
+
+    println!("Config: OK");
+
+    let detected = match source::create(config).and_then(|mut src| src.detect().map(|d| (src.name(), d)).map_err(|e| format!("{}: {}", src.name(), e)))
+    {
+        Ok((name, detection)) => {
+            println!("Source {}: OK", name);
+            detection.addresses
+        }
+        Err(e) => {
+            println!("Source {}", e);
+            code = EXIT_ROUTER;
+            vec![]
+        }
+    };
+
+    if let Some(router) = &config.router
+    {
+        match connect_router(router)
+        {
+            Ok(_) => println!("Router {}: login OK", router.address),
+            Err(e) if e.is_auth() => {
+                println!("Router {}: {}", router.address, e);
+                print_hint(&e);
+                code = EXIT_CONFIG;
+            }
+            Err(e) => {
+                println!("Router {}: {}", router.address, e);
+                code = EXIT_ROUTER;
+            }
+        }
+    }
+
+    let accounts = match provider::create_accounts(&config.accounts)
+    {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            println!("Could not create DNS providers: {}", e);
+            return ExitCode::from(EXIT_UPDATE_FAILED);
+        }
+    };
+
+    let state = State::load(&config.daemon.state_file).unwrap_or_default();
+
+    for account in &accounts
+    {
+        // re-publishing an address verifies the credentials without changing the record, but
+        // only the address the source sees now and the provider last accepted is known to be
+        // published: DNS may answer from a stale cache or a split-horizon view
+        let current = account.records.iter().find_map(|r| {
+            detected.iter().copied().find(|ip| r.matches(ip) && state.is_published(&account.domain, *r, *ip))
+        });
+
+        let Some(current) = current else
+        {
+            println!("Account {}: cannot verify credentials read-only, no {} record is known to be published with the current address",
+                account.domain, account.records.iter().map(|r| r.to_string()).collect::<Vec<_>>().join("/"));
+            continue;
+        };
+
+        let report = account.update(current);
+        match report.result
+        {
+            Ok(_) => println!("Account {} ({}): credentials OK", report.domain, report.provider),
+            Err(e) => {
+                println!("Account {} ({}): {}", report.domain, report.provider, e);
+                code = EXIT_UPDATE_FAILED;
+            }
+        }
+    }
+
+    ExitCode::from(code)
+}
+
+pub fn router_info(config: &Config) -> ExitCode
+{
+    let Some(router) = &config.router else
+    {
+        println!("No [router] section configured");
+        return ExitCode::from(EXIT_CONFIG);
+    };
+
+    match connect_router(router).and_then(|mut sess| sess.get_wan_status())
+    {
+        Ok(status) => {
+            match serde_json::to_string_pretty(&status)
+            {
+                Ok(json) => println!("{}", json),
+                Err(e) => println!("Could not serialize WAN status: {}", e),
+            }
+            ExitCode::from(EXIT_OK)
+        }
+        Err(e) => {
+            println!("Could not get WAN status: {}", e);
+            print_hint(&e);
+            ExitCode::from(EXIT_ROUTER)
+        }
+    }
+}
+
+pub fn funbox_call(config: &Config, service: &str, method: &str, params: &str) -> ExitCode
+{
+    let Some(router) = &config.router else
+    {
+        println!("No [router] section configured");
+        return ExitCode::from(EXIT_CONFIG);
+    };
+
+    let params: serde_json::Value = match serde_json::from_str(params)
+    {
+        Ok(params @ serde_json::Value::Object(_)) => params,
+        Ok(_) => {
+            println!("The parameters must be a JSON object, e.g. '{{\"mibs\":\"dhcp\"}}'");
+            return ExitCode::from(EXIT_CONFIG);
+        }
+        Err(e) => {
+            println!("Could not parse the parameters: {}", e);
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
+
+    match connect_router(router).and_then(|mut sess| sess.call(service, method, params))
+    {
+        Ok(answer) => {
+            match serde_json::to_string_pretty(&answer)
+            {
+                Ok(json) => println!("{}", json),
+                Err(e) => println!("Could not serialize the answer: {}", e),
+            }
+            ExitCode::from(EXIT_OK)
+        }
+        Err(e) => {
+            println!("{}.{} failed: {}", service, method, e);
+            print_hint(&e);
+            ExitCode::from(EXIT_ROUTER)
+        }
+    }
+}
+
+/// Quotes a CSV field when it needs it (RFC 4180)
+fn csv_field(field: &str) -> String
+{
+    if field.contains([',', '"', '\n', '\r'])
+    {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else
+    {
+        field.to_string()
+    }
+}
+
+fn device_row(device: &Device) -> [&str; 6]
+{
+    [&device.mac, &device.ip, &device.name, &device.interface, &device.device_type, if device.active { "yes" } else { "no" }]
+}
+
+pub fn devices(config: &Config, format: DeviceFormat, active_only: bool) -> ExitCode
+{
+    let Some(router) = &config.router else
+    {
+        println!("No [router] section configured");
+        return ExitCode::from(EXIT_CONFIG);
+    };
+
+    let mut devices = match connect_router(router).and_then(|mut sess| sess.get_devices())
+    {
+        Ok(devices) => devices,
+        Err(e) => {
+            println!("Could not list the devices: {}", e);
+            print_hint(&e);
+            return ExitCode::from(EXIT_ROUTER);
+        }
+    };
+
+    if active_only
+    {
+        devices.retain(|d| d.active);
+    }
+
+    const HEADER: [&str; 6] = ["MAC", "IP", "NAME", "INTERFACE", "TYPE", "ACTIVE"];
+
+    match format
+    {
+        DeviceFormat::Json => match serde_json::to_string_pretty(&devices)
+        {
+            Ok(json) => println!("{}", json),
+            Err(e) => println!("Could not serialize the devices: {}", e),
+        },
+        DeviceFormat::Csv => {
+            println!("{}", HEADER.map(str::to_lowercase).join(","));
+            for device in &devices
+            {
+                println!("{}", device_row(device).map(csv_field).join(","));
+            }
+        }
+        DeviceFormat::Table => {
+            let mut widths = HEADER.map(str::len);
+            for device in &devices
+            {
+                for (width, field) in widths.iter_mut().zip(device_row(device))
+                {
+                    *width = (*width).max(field.chars().count());
+                }
+            }
+
+            let print_row = |row: [&str; 6]| {
+                let cells: Vec<String> = row.iter().zip(widths).map(|(field, width)| format!("{:<width$}", field, width = width)).collect();
+                println!("{}", cells.join("  ").trim_end());
+            };
+
+            print_row(HEADER);
+            for device in &devices
+            {
+                print_row(device_row(device));
+            }
+            println!("{} devices, {} connected", devices.len(), devices.iter().filter(|d| d.active).count());
+        }
+    }
+
+    ExitCode::from(EXIT_OK)
+}
+
+pub fn reconcile(config: &Config, dry_run: bool) -> ExitCode
+{
+    let Some(router) = &config.router else
+    {
+        println!("No [router] section configured");
+        return ExitCode::from(EXIT_CONFIG);
+    };
+
+    if !reconcile::enabled(config)
+    {
+        println!("Nothing to reconcile, enable port_forwards, pinholes and/or static_leases in [reconcile]");
+        return ExitCode::from(EXIT_CONFIG);
+    }
+
+    let mut session = match connect_router(router)
+    {
+        Ok(session) => session,
+        Err(e) => {
+            println!("Router {}: {}", router.address, e);
+            print_hint(&e);
+            return ExitCode::from(EXIT_ROUTER);
+        }
+    };
+
+    let report = reconcile::run(&mut session, config, dry_run || config.reconcile.dry_run);
+
+    if report.changes.is_empty() && report.errors.is_empty()
+    {
+        println!("Router is up to date");
+    }
+    for line in report.changes.iter().chain(report.errors.iter())
+    {
+        println!("{}", line);
+    }
+
+    ExitCode::from(if report.errors.is_empty() { EXIT_OK } else { EXIT_ROUTER })
+}
@@ -0,0 +1,694 @@
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use crate::funbox::firewall::Protocol;
+use crate::util::{classify, AddressClass};
+
+const CONFIG_DIR_NAME: &str = "dynhost";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config
+{
+    #[serde(default)]
+    pub source: SourceConfig,
+    pub router: Option<RouterConfig>,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub reconcile: ReconcileConfig,
+    #[serde(default)]
+    pub devices: DevicesConfig,
+    #[serde(default, rename = "account")]
+    pub accounts: Vec<AccountConfig>,
+    #[serde(default, rename = "port_forward")]
+    pub port_forwards: Vec<PortForwardConfig>,
+    #[serde(default, rename = "pinhole")]
+    pub pinholes: Vec<PinholeConfig>,
+    #[serde(default, rename = "static_lease")]
+    pub static_leases: Vec<StaticLeaseConfig>,
+}
+
+/// Where the public IP comes from
+#[derive(Deserialize, Clone, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SourceConfig
+{
+    /// Ask the FunBox configured in `[router]`
+    #[default]
+    Funbox,
+    /// Ask "what is my IP" web services answering with the address as plain text
+    Http
+    {
+        /// Defaults to ipify when `ipv6_url` is not set either, see [`SourceConfig::http_urls`]
+        #[serde(default)]
+        ipv4_url: Option<String>,
+        #[serde(default)]
+        ipv6_url: Option<String>,
+    },
+}
+
+impl SourceConfig
+{
+    /// IPv4 and IPv6 URLs of the http source, IPv4 only from ipify when neither is configured
+    pub fn http_urls(&self) -> (Option<String>, Option<String>)
+    {
+        match self
+        {
+            SourceConfig::Http { ipv4_url: None, ipv6_url: None } => (default_http_ipv4_url(), None),
+            SourceConfig::Http { ipv4_url, ipv6_url } => (ipv4_url.clone(), ipv6_url.clone()),
+            SourceConfig::Funbox => (None, None),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RouterConfig
+{
+    /// Host name or address, or `auto` to look for the router on the LAN
+    #[serde(default = "default_router_address")]
+    pub address: String,
+    #[serde(default = "default_router_username")]
+    pub username: String,
+    pub password: String,
+    /// Where the router login is kept across restarts (owner-only), empty to log in on every start
+    #[serde(default = "default_session_cache")]
+    pub session_cache: PathBuf,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig
+{
+    #[serde(default = "default_log_path")]
+    pub path: String,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig
+{
+    /// Seconds between two public IP checks when the source cannot push changes, used right
+    /// after a change or an error
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval: u64,
+    /// Upper bound in seconds the poll interval grows to while the address stays the same
+    #[serde(default = "default_poll_interval_max")]
+    pub poll_interval_max: u64,
+    /// Wait for the router to push WAN change events instead of polling
+    #[serde(default = "default_events")]
+    pub events: bool,
+    /// Seconds after which the public IP is checked again even without any event
+    #[serde(default = "default_event_timeout")]
+    pub event_timeout: u64,
+    /// Seconds before the first retry of a failed account update, doubled on every further failure
+    #[serde(default = "default_update_retry_base")]
+    pub update_retry_base: u64,
+    /// Upper bound in seconds for the delay between two retries of a failed account update
+    #[serde(default = "default_update_retry_max")]
+    pub update_retry_max: u64,
+    /// Where the last published address of every record is kept across restarts
+    #[serde(default = "default_state_file")]
+    pub state_file: PathBuf,
+}
+
+impl RouterConfig
+{
+    pub fn is_auto(&self) -> bool
+    {
+        self.address == "auto"
+    }
+
+    pub fn session_cache(&self) -> Option<&Path>
+    {
+        Some(self.session_cache.as_path()).filter(|p| !p.as_os_str().is_empty())
+    }
+}
+
+/// What to do with an address that cannot be reached from the internet
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnreachablePolicy
+{
+    /// Leave the records as they are until a reachable address comes back
+    #[default]
+    Skip,
+    /// Publish it anyway, e.g. when the ISP forwards ports through its CGNAT
+    Publish,
+    /// Publish the fallback address of the same family instead, e.g. a relay host
+    Fallback,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyConfig
+{
+    /// Applied to private, CGNAT and other bogon addresses, and to anything reported
+    /// while the router is on its 4G backup link
+    #[serde(default)]
+    pub unreachable: UnreachablePolicy,
+    pub fallback_ipv4: Option<Ipv4Addr>,
+    pub fallback_ipv6: Option<Ipv6Addr>,
+}
+
+impl PolicyConfig
+{
+    pub fn fallback_for(&self, ip: &IpAddr) -> Option<IpAddr>
+    {
+        match ip
+        {
+            IpAddr::V4(_) => self.fallback_ipv4.map(IpAddr::V4),
+            IpAddr::V6(_) => self.fallback_ipv6.map(IpAddr::V6),
+        }
+    }
+}
+
+/// Which router entries the daemon keeps in line with the config
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReconcileConfig
+{
+    /// Create, update and delete the NAT rules to match the `[[port_forward]]` sections
+    #[serde(default)]
+    pub port_forwards: bool,
+    /// Create, update and delete the IPv6 pinholes to match the `[[pinhole]]` sections
+    #[serde(default)]
+    pub pinholes: bool,
+    /// Create and update the DHCP reservations to match the `[[static_lease]]` sections
+    #[serde(default)]
+    pub static_leases: bool,
+    /// Only log the changes that would be made
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Seconds between two checks, so rules lost to a factory reset come back
+    #[serde(default = "default_reconcile_interval")]
+    pub interval: u64,
+}
+
+/// Periodic look at the LAN devices known by the router
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DevicesConfig
+{
+    /// Log the devices joining and leaving the LAN
+    #[serde(default)]
+    pub snapshot: bool,
+    /// Seconds between two snapshots
+    #[serde(default = "default_devices_interval")]
+    pub interval: u64,
+}
+
+/// A NAT rule from the WAN to a LAN host
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PortForwardConfig
+{
+    /// Unique name, part of the rule id on the router
+    pub name: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+    pub external_port: u16,
+    pub internal_host: Ipv4Addr,
+    /// Defaults to `external_port`
+    pub internal_port: Option<u16>,
+}
+
+impl PortForwardConfig
+{
+    pub fn internal_port(&self) -> u16
+    {
+        self.internal_port.unwrap_or(self.external_port)
+    }
+}
+
+/// An IPv6 firewall opening to a LAN host
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PinholeConfig
+{
+    /// Unique name, part of the pinhole id on the router
+    pub name: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+    pub port: u16,
+    /// Host part of the address, e.g. `::211:22ff:fe33:4455`; the prefix is the delegated one,
+    /// so the pinhole follows it when it changes
+    pub interface_id: Ipv6Addr,
+}
+
+/// A fixed LAN address for a host, handed out by the router DHCP server
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StaticLeaseConfig
+{
+    /// Unique name, only used in the logs
+    pub name: String,
+    /// e.g. `00:11:22:33:44:55`
+    pub mac: String,
+    pub ip: Ipv4Addr,
+}
+
+impl StaticLeaseConfig
+{
+    /// The MAC address the way the router reports it
+    pub fn mac(&self) -> String
+    {
+        self.mac.to_ascii_uppercase()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig
+{
+    pub domain: String,
+    #[serde(default)]
+    pub provider: ProviderKind,
+    /// Update endpoint, required by the dyndns2 provider
+    pub server: Option<String>,
+    /// Which records to keep up to date, A (IPv4) and/or AAAA (IPv6)
+    #[serde(default = "default_records")]
+    pub records: Vec<RecordType>,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum RecordType
+{
+    A,
+    AAAA,
+}
+
+impl RecordType
+{
+    pub fn family(&self) -> &'static str
+    {
+        match self
+        {
+            RecordType::A => "IPv4",
+            RecordType::AAAA => "IPv6",
+        }
+    }
+
+    pub fn matches(&self, ip: &IpAddr) -> bool
+    {
+        match self
+        {
+            RecordType::A => ip.is_ipv4(),
+            RecordType::AAAA => ip.is_ipv6(),
+        }
+    }
+}
+
+impl std::fmt::Display for RecordType
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Which service publishes an account's record
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind
+{
+    /// OVH DynHost
+    #[default]
+    Ovh,
+    /// Any registrar speaking the dyndns2 protocol, set `server` to its update URL
+    Dyndns2,
+}
+
+fn default_http_ipv4_url() -> Option<String> { Some("https://api.ipify.org".to_string()) }
+fn default_records() -> Vec<RecordType> { vec![RecordType::A] }
+fn default_router_address() -> String { "192.168.1.1".to_string() }
+fn default_router_username() -> String { "admin".to_string() }
+fn default_session_cache() -> PathBuf { PathBuf::from("/var/lib/dynhost/session.json") }
+fn default_log_path() -> String { "/var/log/dynhost.log".to_string() }
+fn default_poll_interval() -> u64 { 15 }
+fn default_poll_interval_max() -> u64 { 300 }
+fn default_events() -> bool { true }
+fn default_event_timeout() -> u64 { 600 }
+fn default_update_retry_base() -> u64 { 60 }
+fn default_update_retry_max() -> u64 { 3600 }
+fn default_reconcile_interval() -> u64 { 3600 }
+fn default_devices_interval() -> u64 { 300 }
+fn default_state_file() -> PathBuf { PathBuf::from("/var/lib/dynhost/state.json") }
+
+impl Default for LoggingConfig
+{
+    fn default() -> Self
+    {
+        LoggingConfig { path: default_log_path() }
+    }
+}
+
+impl Default for DevicesConfig
+{
+    fn default() -> Self
+    {
+        DevicesConfig { snapshot: false, interval: default_devices_interval() }
+    }
+}
+
+impl Default for ReconcileConfig
+{
+    fn default() -> Self
+    {
+        ReconcileConfig { port_forwards: false, pinholes: false, static_leases: false, dry_run: false, interval: default_reconcile_interval() }
+    }
+}
+
+impl Default for DaemonConfig
+{
+    fn default() -> Self
+    {
+        DaemonConfig {
+            poll_interval: default_poll_interval(),
+            poll_interval_max: default_poll_interval_max(),
+            events: default_events(),
+            event_timeout: default_event_timeout(),
+            update_retry_base: default_update_retry_base(),
+            update_retry_max: default_update_retry_max(),
+            state_file: default_state_file(),
+        }
+    }
+}
+
+impl Config
+{
+    /// Loads the config from `path` if given, otherwise from the first existing
+    /// of `$XDG_CONFIG_HOME/dynhost/config.toml` and `/etc/dynhost/config.toml`
+    pub fn load(path: Option<&Path>) -> Result<(Config, PathBuf), String>
+    {
+        let path = match path
+        {
+            Some(path) => path.to_path_buf(),
+            None => Self::find().ok_or_else(|| format!("No config file found, looked in: {}",
+                Self::search_paths().iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")))?,
+        };
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read '{}': {}", path.display(), e))?;
+
+        let config = Self::parse(&content)
+            .map_err(|e| format!("Invalid config '{}': {}", path.display(), e))?;
+
+        Ok((config, path))
+    }
+
+    pub fn parse(content: &str) -> Result<Config, String>
+    {
+        let config: Config = toml::from_str(content).map_err(|e| e.to_string())?;
+
+        let errors = config.validate();
+        if !errors.is_empty()
+        {
+            return Err(format!("\n  {}", errors.join("\n  ")));
+        }
+
+        Ok(config)
+    }
+
+    fn search_paths() -> Vec<PathBuf>
+    {
+        let mut paths = vec![];
+
+        if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty())
+        {
+            paths.push(PathBuf::from(xdg).join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME));
+        }
+        else if let Some(home) = std::env::var_os("HOME").filter(|v| !v.is_empty())
+        {
+            paths.push(PathBuf::from(home).join(".config").join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME));
+        }
+
+        paths.push(PathBuf::from("/etc").join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME));
+
+        paths
+    }
+
+    fn find() -> Option<PathBuf>
+    {
+        Self::search_paths().into_iter().find(|p| p.is_file())
+    }
+
+    /// Returns one message per invalid field
+    fn validate(&self) -> Vec<String>
+    {
+        let mut errors = vec![];
+
+        match &self.source
+        {
+            SourceConfig::Funbox => {
+                if self.router.is_none()
+                {
+                    errors.push("router: the funbox source requires a [router] section".to_string());
+                }
+            }
+            SourceConfig::Http { ipv4_url, ipv6_url } => {
+                for (field, url) in [("ipv4_url", ipv4_url), ("ipv6_url", ipv6_url)]
+                {
+                    if let Some(url) = url
+                    {
+                        if !(url.starts_with("http://") || url.starts_with("https://"))
+                        {
+                            errors.push(format!("source.{}: '{}' is not an http(s) URL", field, url));
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(router) = &self.router
+        {
+            if router.address.trim().is_empty()
+            {
+                errors.push("router.address: must not be empty".to_string());
+            }
+            if router.username.trim().is_empty()
+            {
+                errors.push("router.username: must not be empty".to_string());
+            }
+            if router.password.is_empty()
+            {
+                errors.push("router.password: must not be empty".to_string());
+            }
+        }
+
+        if self.logging.path.trim().is_empty()
+        {
+            errors.push("logging.path: must not be empty".to_string());
+        }
+
+        if self.daemon.poll_interval == 0
+        {
+            errors.push("daemon.poll_interval: must be at least 1 second".to_string());
+        }
+        if self.daemon.events && self.daemon.event_timeout == 0
+        {
+            errors.push("daemon.event_timeout: must be at least 1 second".to_string());
+        }
+        if self.daemon.poll_interval_max < self.daemon.poll_interval
+        {
+            errors.push("daemon.poll_interval_max: must not be lower than daemon.poll_interval".to_string());
+        }
+        if self.daemon.update_retry_base == 0
+        {
+            errors.push("daemon.update_retry_base: must be at least 1 second".to_string());
+        }
+        if self.daemon.update_retry_max < self.daemon.update_retry_base
+        {
+            errors.push("daemon.update_retry_max: must not be lower than daemon.update_retry_base".to_string());
+        }
+        if self.daemon.state_file.as_os_str().is_empty()
+        {
+            errors.push("daemon.state_file: must not be empty".to_string());
+        }
+
+        if self.policy.unreachable == UnreachablePolicy::Fallback && self.policy.fallback_ipv4.is_none() && self.policy.fallback_ipv6.is_none()
+        {
+            errors.push("policy.unreachable: 'fallback' requires 'fallback_ipv4' and/or 'fallback_ipv6'".to_string());
+        }
+        for (field, ip) in [("fallback_ipv4", self.policy.fallback_ipv4.map(IpAddr::V4)), ("fallback_ipv6", self.policy.fallback_ipv6.map(IpAddr::V6))]
+        {
+            if let Some(ip) = ip
+            {
+                let class = classify(&ip);
+                if !class.is_global()
+                {
+                    errors.push(format!("policy.{}: {} is a {}", field, ip, class.description()));
+                }
+            }
+        }
+
+        for (field, enabled) in [("port_forwards", self.reconcile.port_forwards), ("pinholes", self.reconcile.pinholes),
+            ("static_leases", self.reconcile.static_leases)]
+        {
+            if enabled && self.router.is_none()
+            {
+                errors.push(format!("reconcile.{}: requires a [router] section", field));
+            }
+        }
+        if self.reconcile.interval < 60
+        {
+            errors.push("reconcile.interval: must be at least 60 seconds".to_string());
+        }
+
+        if self.devices.snapshot && self.router.is_none()
+        {
+            errors.push("devices.snapshot: requires a [router] section".to_string());
+        }
+        if self.devices.interval < 60
+        {
+            errors.push("devices.interval: must be at least 60 seconds".to_string());
+        }
+
+        for (idx, forward) in self.port_forwards.iter().enumerate()
+        {
+            if !is_rule_name(&forward.name)
+            {
+                errors.push(format!("port_forward[{}].name: '{}' must be non-empty and only contain letters, digits, '-' and '_'", idx, forward.name));
+            }
+            if self.port_forwards[..idx].iter().any(|other| other.name == forward.name)
+            {
+                errors.push(format!("port_forward[{}].name: '{}' is used twice", idx, forward.name));
+            }
+            if forward.external_port == 0 || forward.internal_port == Some(0)
+            {
+                errors.push(format!("port_forward[{}]: ports must not be 0", idx));
+            }
+            if classify(&IpAddr::V4(forward.internal_host)) != AddressClass::Private
+            {
+                errors.push(format!("port_forward[{}].internal_host: {} is not a LAN address", idx, forward.internal_host));
+            }
+        }
+
+        for (idx, pinhole) in self.pinholes.iter().enumerate()
+        {
+            if !is_rule_name(&pinhole.name)
+            {
+                errors.push(format!("pinhole[{}].name: '{}' must be non-empty and only contain letters, digits, '-' and '_'", idx, pinhole.name));
+            }
+            if self.pinholes[..idx].iter().any(|other| other.name == pinhole.name)
+            {
+                errors.push(format!("pinhole[{}].name: '{}' is used twice", idx, pinhole.name));
+            }
+            if pinhole.port == 0
+            {
+                errors.push(format!("pinhole[{}].port: must not be 0", idx));
+            }
+            // only the low 64 bits are used, anything above would silently be dropped
+            let id = u128::from(pinhole.interface_id);
+            if id >> 64 != 0 || id == 0
+            {
+                errors.push(format!("pinhole[{}].interface_id: {} must be a non-zero host part such as ::211:22ff:fe33:4455", idx, pinhole.interface_id));
+            }
+        }
+
+        for (idx, lease) in self.static_leases.iter().enumerate()
+        {
+            let earlier = &self.static_leases[..idx];
+
+            if earlier.iter().any(|other| other.name == lease.name)
+            {
+                errors.push(format!("static_lease[{}].name: '{}' is used twice", idx, lease.name));
+            }
+            if !is_mac_address(&lease.mac)
+            {
+                errors.push(format!("static_lease[{}].mac: '{}' is not a MAC address such as 00:11:22:33:44:55", idx, lease.mac));
+            }
+            else if earlier.iter().any(|other| other.mac() == lease.mac())
+            {
+                errors.push(format!("static_lease[{}].mac: {} is used twice", idx, lease.mac));
+            }
+            if classify(&IpAddr::V4(lease.ip)) != AddressClass::Private
+            {
+                errors.push(format!("static_lease[{}].ip: {} is not a LAN address", idx, lease.ip));
+            }
+            else if earlier.iter().any(|other| other.ip == lease.ip)
+            {
+                errors.push(format!("static_lease[{}].ip: {} is used twice", idx, lease.ip));
+            }
+        }
+
+        if self.accounts.is_empty()
+        {
+            errors.push("account: at least one [[account]] section is required".to_string());
+        }
+
+        for (idx, account) in self.accounts.iter().enumerate()
+        {
+            let name = format!("account[{}]", idx);
+
+            if account.domain.trim().is_empty()
+            {
+                errors.push(format!("{}.domain: must not be empty", name));
+            }
+            else if account.domain.contains(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+            {
+                errors.push(format!("{}.domain: '{}' is not a valid host name", name, account.domain));
+            }
+            match (account.provider, &account.server)
+            {
+                (ProviderKind::Dyndns2, None) => {
+                    errors.push(format!("{}.server: required by the dyndns2 provider", name));
+                }
+                (ProviderKind::Dyndns2, Some(server)) if !(server.starts_with("http://") || server.starts_with("https://")) => {
+                    errors.push(format!("{}.server: '{}' is not an http(s) URL", name, server));
+                }
+                (ProviderKind::Ovh, Some(_)) => {
+                    errors.push(format!("{}.server: not used by the ovh provider", name));
+                }
+                _ => {}
+            }
+            if account.records.is_empty()
+            {
+                errors.push(format!("{}.records: must contain \"A\" and/or \"AAAA\"", name));
+            }
+            else if let Some(record) = account.records.iter().enumerate().find_map(|(i, r)| account.records[..i].contains(r).then_some(r))
+            {
+                errors.push(format!("{}.records: '{}' is listed more than once", name, record));
+            }
+            if account.username.trim().is_empty()
+            {
+                errors.push(format!("{}.username: must not be empty", name));
+            }
+            if account.password.is_empty()
+            {
+                errors.push(format!("{}.password: must not be empty", name));
+            }
+
+            if self.accounts[..idx].iter().any(|other| other.domain == account.domain)
+            {
+                errors.push(format!("{}.domain: '{}' is declared more than once", name, account.domain));
+            }
+        }
+
+        errors
+    }
+}
+
+/// Names end up in router ids, which only take these characters
+fn is_rule_name(name: &str) -> bool
+{
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Six colon separated hex bytes
+fn is_mac_address(mac: &str) -> bool
+{
+    let bytes: Vec<&str> = mac.split(':').collect();
+
+    bytes.len() == 6 && bytes.iter().all(|b| b.len() == 2 && b.chars().all(|c| c.is_ascii_hexdigit()))
+}
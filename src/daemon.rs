@@ -0,0 +1,839 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use chrono::{Local, Utc};
+
+use crate::cli::{EXIT_CONFIG, EXIT_OK, EXIT_ROUTER, EXIT_UPDATE_FAILED};
+use crate::config::{Config, RecordType};
+use crate::funbox::{discovery, Session};
+use crate::funbox::devices::Device;
+use crate::inventory;
+use crate::log::Logger;
+use crate::poll::PollScheduler;
+use crate::policy;
+use crate::provider::{self, Account};
+use crate::reconcile;
+use crate::retry::RetryScheduler;
+use crate::source::{self, ChangeWait, Detection, IpSource, SourceError};
+use crate::signals::Signals;
+use crate::state::{Outage, PollState, State};
+use crate::util::format_duration;
+
+const RECORD_TYPES: [RecordType; 2] = [RecordType::A, RecordType::AAAA];
+
+type RecordKey = (String, RecordType);
+
+/// Longest single event long-poll, so signals are handled within a reasonable time
+const EVENT_WAIT_STEP: Duration = Duration::from_secs(15);
+/// How long to poll before trying to subscribe to events again after a failure
+const EVENT_RESUBSCRIBE_DELAY: Duration = Duration::from_secs(600);
+
+fn create_accounts(config: &Config) -> Result<Vec<Account>, String>
+{
+    provider::create_accounts(&config.accounts).map_err(|e| format!("Could not create DNS providers: {}", e))
+}
+
+/// Loads the state file, an unreadable one is logged and replaced by an empty state
+fn load_state(config: &Config, logger: &mut Logger) -> State
+{
+    match State::load(&config.daemon.state_file)
+    {
+        Ok(state) => state,
+        Err(e) => {
+            logger.error(format!("{}; starting with an empty state", e).as_str());
+            State::default()
+        }
+    }
+}
+
+/// Resolves `address = "auto"` in `[router]`, caching the discovered address in the state
+fn resolve_router(config: &mut Config, state: &mut State, logger: &mut Logger) -> Result<(), String>
+{
+    let Some(router) = config.router.as_mut() else
+    {
+        return Ok(());
+    };
+
+    if let Some(note) = discovery::resolve(router, &mut state.router)?
+    {
+        logger.info(note.as_str());
+
+        if let Err(e) = state.save(&config.daemon.state_file)
+        {
+            logger.error(e.as_str());
+        }
+    }
+
+    Ok(())
+}
+
+/// Last address seen for one record type
+#[derive(Default)]
+struct Tracked
+{
+    ip: Option<IpAddr>,
+    /// The source stopped reporting this address family, logged once
+    missing: bool,
+}
+
+/// Publishes detected addresses, tracking changes per record type and retrying failed accounts
+struct Updater
+{
+    accounts: Vec<Account>,
+    retries: RetryScheduler<RecordKey>,
+    tracked: HashMap<RecordType, Tracked>,
+    state: State,
+    state_path: PathBuf,
+    /// Policy notes of the last detection, only changes are logged
+    policy_notes: Vec<String>,
+}
+
+impl Updater
+{
+    fn new(config: &Config, accounts: Vec<Account>, state: State) -> Updater
+    {
+        Updater {
+            accounts,
+            state,
+            state_path: config.daemon.state_file.clone(),
+            retries: RetryScheduler::new(
+                Duration::from_secs(config.daemon.update_retry_base),
+                Duration::from_secs(config.daemon.update_retry_max)),
+            tracked: HashMap::new(),
+            policy_notes: vec![],
+        }
+    }
+
+    /// Swaps in accounts from a reloaded config; pending retries and disabled accounts are
+    /// forgotten and every account is compared against the state on the next detection
+    fn reload(&mut self, config: &Config, accounts: Vec<Account>)
+    {
+        self.accounts = accounts;
+        self.retries = RetryScheduler::new(
+            Duration::from_secs(config.daemon.update_retry_base),
+            Duration::from_secs(config.daemon.update_retry_max));
+        self.tracked.clear();
+        self.policy_notes.clear();
+        self.state_path = config.daemon.state_file.clone();
+    }
+
+    /// Records the start of an outage, provider updates are skipped until it ends
+    fn wan_down(&mut self, logger: &mut Logger)
+    {
+        if self.state.start_outage()
+        {
+            logger.error("Router reports the internet connection is down, pausing updates");
+            self.save_state(logger);
+        }
+    }
+
+    /// Closes the ongoing outage, if any, and logs a summary
+    fn wan_up(&mut self, logger: &mut Logger)
+    {
+        let Some(outage) = self.state.end_outage() else
+        {
+            return;
+        };
+
+        let month_ago = Utc::now() - chrono::Duration::days(30);
+        let recent: Vec<&Outage> = self.state.outages.iter().filter(|o| o.started_at >= month_ago).collect();
+        let total = recent.iter().fold(chrono::Duration::zero(), |acc, o| acc + o.duration());
+
+        logger.info(format!("Internet connection is back after {} (down since {}); {} outages in the last 30 days, {} in total",
+            format_duration(outage.duration()),
+            outage.started_at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+            recent.len(),
+            format_duration(total)).as_str());
+
+        self.save_state(logger);
+    }
+
+    /// Publishes the next poll time through the state file
+    fn record_poll(&mut self, mode: &str, interval: Duration, poller: &PollScheduler, logger: &mut Logger)
+    {
+        let poll = poller.next_poll().map(|next_poll| PollState {
+            mode: mode.to_string(),
+            interval_secs: interval.as_secs(),
+            next_poll,
+        });
+
+        if let Err(e) = PollState::save(poll.as_ref(), &self.state_path)
+        {
+            logger.error(e.as_str());
+        }
+    }
+
+    fn record_devices(&mut self, devices: &[Device], logger: &mut Logger)
+    {
+        let lines = inventory::compare(&mut self.state.devices, devices);
+
+        for line in &lines
+        {
+            logger.info(line.as_str());
+        }
+
+        self.save_state(logger);
+    }
+
+    /// When the next failed update should be retried
+    fn next_retry(&self) -> Option<Instant>
+    {
+        self.retries.next_due()
+    }
+
+    fn save_state(&self, logger: &mut Logger)
+    {
+        if let Err(e) = self.state.save(&self.state_path)
+        {
+            logger.error(e.as_str());
+        }
+    }
+
+    /// Logs what the `[policy]` section did with unreachable addresses, once per change, so a
+    /// lasting 4G backup or CGNAT address does not fill the log
+    fn report_policy(&mut self, notes: Vec<String>, logger: &mut Logger)
+    {
+        if notes == self.policy_notes
+        {
+            return;
+        }
+
+        for note in &notes
+        {
+            logger.error(note.as_str());
+        }
+        if notes.is_empty() && !self.policy_notes.is_empty()
+        {
+            logger.info("Public addresses are reachable from the internet again");
+        }
+
+        self.policy_notes = notes;
+    }
+
+    fn wants(&self, record: RecordType) -> bool
+    {
+        self.accounts.iter().any(|acc| acc.records.contains(&record))
+    }
+
+    /// Whether the detection has an address for at least one record type the accounts need
+    fn has_usable_address(&self, detection: &Detection) -> bool
+    {
+        RECORD_TYPES.iter().any(|r| self.wants(*r) && detection.address_for(*r).is_some())
+    }
+
+    fn handle(&mut self, detection: &Detection, logger: &mut Logger)
+    {
+        for record in RECORD_TYPES
+        {
+            if !self.wants(record)
+            {
+                continue;
+            }
+
+            let current = detection.address_for(record);
+            let tracked = self.tracked.entry(record).or_default();
+
+            match current
+            {
+                // report_policy already told why
+                None if detection.is_withheld(record) => {}
+                None => {
+                    if !tracked.missing
+                    {
+                        logger.error(format!("{} did not report a public {} address, {} records are left as they are",
+                            detection.source, record.family(), record).as_str());
+                        tracked.missing = true;
+                    }
+                }
+                Some(ip) if Some(ip) != tracked.ip => {
+                    tracked.ip = Some(ip);
+                    tracked.missing = false;
+
+                    logger.info(format!("Detected that public {} changed to {} ({}{}), updating...", record.family(), ip,
+                        detection.source, detection.details.as_deref().map(|d| format!(", {}", d)).unwrap_or_default()).as_str());
+
+                    // a new address supersedes every pending retry of this record type
+                    self.retries.clear_pending(|(_, r)| *r == record);
+
+                    for idx in 0..self.accounts.len()
+                    {
+                        let domain = self.accounts[idx].domain.clone();
+
+                        if !self.accounts[idx].records.contains(&record) || self.retries.is_blocked(&(domain.clone(), record))
+                        {
+                            continue;
+                        }
+
+                        if self.state.is_published(&domain, record, ip)
+                        {
+                            logger.info(format!("{} {} is already published as {}, skipping", domain, record, ip).as_str());
+                            continue;
+                        }
+
+                        self.publish(idx, record, ip, logger);
+                    }
+                }
+                Some(ip) => {
+                    tracked.missing = false;
+
+                    let due = self.retries.due();
+
+                    for idx in 0..self.accounts.len()
+                    {
+                        if due.contains(&(self.accounts[idx].domain.clone(), record))
+                        {
+                            self.publish(idx, record, ip, logger);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Publishes `ip` to the account and schedules a retry if it failed
+    fn publish(&mut self, idx: usize, record: RecordType, ip: IpAddr, logger: &mut Logger)
+    {
+        let account = &self.accounts[idx];
+        let key = (account.domain.clone(), record);
+
+        let report = account.update(ip);
+        report.log(logger);
+
+        match &report.result
+        {
+            Ok(_) => self.state.record_success(&account.domain, record, ip, report.response()),
+            Err(_) => self.state.record_failure(&account.domain, record, report.response()),
+        }
+
+        self.save_state(logger);
+
+        match &report.result
+        {
+            Ok(_) => self.retries.record_success(&key),
+            Err(e) => match self.retries.record_failure(&key, e.kind())
+            {
+                Some(delay) => {
+                    logger.info(format!("Retrying {} {} in {}s", account.domain, record, delay.as_secs()).as_str());
+                }
+                None => {
+                    logger.error(format!("Giving up on {} {} until the config changes", account.domain, record).as_str());
+                }
+            },
+        }
+    }
+}
+
+/// Single detect-and-update pass, meant for cron jobs and systemd timers
+pub fn once(mut config: Config, logger: &mut Logger) -> ExitCode
+{
+    let config = &mut config;
+
+    let accounts = match create_accounts(config)
+    {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            logger.error(e.as_str());
+            return ExitCode::from(EXIT_UPDATE_FAILED);
+        }
+    };
+
+    let mut state = load_state(config, logger);
+
+    if let Err(e) = resolve_router(config, &mut state, logger)
+    {
+        logger.error(e.as_str());
+        return ExitCode::from(EXIT_ROUTER);
+    }
+
+    let mut ip_source = match source::create(config)
+    {
+        Ok(src) => src,
+        Err(e) => {
+            logger.error(e.as_str());
+            return ExitCode::from(EXIT_ROUTER);
+        }
+    };
+
+    if let Ok(Some(false)) = ip_source.connectivity()
+    {
+        logger.error(format!("{} reports the internet connection is down, not updating", ip_source.name()).as_str());
+        return ExitCode::from(EXIT_ROUTER);
+    }
+
+    let mut detection = match ip_source.detect()
+    {
+        Ok(detection) => detection,
+        Err(e) => {
+            logger.error(format!("Could not get public IP from {}: {}", ip_source.name(), e).as_str());
+            return ExitCode::from(EXIT_ROUTER);
+        }
+    };
+
+    for note in policy::apply(&mut detection, &config.policy)
+    {
+        logger.error(note.as_str());
+    }
+
+    let mut failed = 0;
+    let mut missing = false;
+
+    for account in &accounts
+    {
+        for record in &account.records
+        {
+            let Some(ip) = detection.address_for(*record) else
+            {
+                // the policy note above already told why, this is not an error
+                if detection.is_withheld(*record)
+                {
+                    continue;
+                }
+
+                logger.error(format!("{} did not report a public {} address, skipping {} {}",
+                    detection.source, record.family(), account.domain, record).as_str());
+                missing = true;
+                continue;
+            };
+
+            if state.is_published(&account.domain, *record, ip)
+            {
+                logger.info(format!("{} {} is already published as {}, skipping", account.domain, record, ip).as_str());
+                continue;
+            }
+
+            let report = account.update(ip);
+            report.log(logger);
+
+            match &report.result
+            {
+                Ok(_) => state.record_success(&account.domain, *record, ip, report.response()),
+                Err(_) => {
+                    state.record_failure(&account.domain, *record, report.response());
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if let Err(e) = state.save(&config.daemon.state_file)
+    {
+        logger.error(e.as_str());
+    }
+
+    if failed > 0
+    {
+        ExitCode::from(EXIT_UPDATE_FAILED)
+    }
+    else if missing
+    {
+        ExitCode::from(EXIT_ROUTER)
+    }
+    else
+    {
+        ExitCode::from(EXIT_OK)
+    }
+}
+
+fn create_poller(config: &Config) -> PollScheduler
+{
+    PollScheduler::new(Duration::from_secs(config.daemon.poll_interval), Duration::from_secs(config.daemon.poll_interval_max))
+}
+
+/// Re-reads the config after SIGHUP; the IP source (and with it the router session) is kept,
+/// so changes to `[source]` and `[router]` need a restart
+fn reload(config: &mut Config, config_path: &Path, updater: &mut Updater, logger: &mut Logger)
+{
+    let mut new_config = match Config::load(Some(config_path))
+    {
+        Ok((new_config, _)) => new_config,
+        Err(e) => {
+            logger.error(format!("Could not reload config, keeping the current one: {}", e).as_str());
+            return;
+        }
+    };
+
+    let accounts = match create_accounts(&new_config)
+    {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            logger.error(format!("{}, keeping the current config", e).as_str());
+            return;
+        }
+    };
+
+    // an auto-discovered router is kept, it is only looked for again on restart
+    if let (Some(router), Some(cached)) = (new_config.router.as_mut(), updater.state.router.as_ref())
+    {
+        if router.is_auto()
+        {
+            router.address = cached.address.clone();
+        }
+    }
+
+    if new_config.source != config.source || new_config.router != config.router
+    {
+        logger.error("The [source] and [router] sections cannot be reloaded, restart to apply them");
+    }
+
+    if new_config.logging.path != config.logging.path
+    {
+        match Logger::new(new_config.logging.path.as_str())
+        {
+            Ok(new_logger) => {
+                logger.info(format!("Logging to {} from now on", new_config.logging.path).as_str());
+                *logger = new_logger;
+            }
+            Err(e) => logger.error(format!("Could not open log file '{}', keeping the current one: {}", new_config.logging.path, e).as_str()),
+        }
+    }
+
+    updater.reload(&new_config, accounts);
+    *config = new_config;
+
+    logger.info(format!("Reloaded config from '{}' with {} accounts", config_path.display(), config.accounts.len()).as_str());
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum EventMode
+{
+    /// Wait for the source to push changes
+    Subscribed,
+    /// Events are disabled or the source cannot push them
+    Polling,
+    /// Subscribing failed, poll until EVENT_RESUBSCRIBE_DELAY passed
+    FailedAt(Instant),
+}
+
+enum EventWait
+{
+    /// A change was reported, the timeout passed or a signal arrived
+    Done,
+    /// The source cannot push events, poll instead
+    Unsupported,
+    Failed(SourceError),
+}
+
+/// Blocks until the source reports a change, `timeout` passes or a signal arrives
+fn wait_for_event(ip_source: &mut Box<dyn IpSource>, signals: &Signals, timeout: Duration) -> EventWait
+{
+    let end = Instant::now() + timeout;
+
+    while !signals.interrupted()
+    {
+        let now = Instant::now();
+        if now >= end
+        {
+            break;
+        }
+
+        match ip_source.wait_for_change(EVENT_WAIT_STEP.min(end - now))
+        {
+            Ok(ChangeWait::Changed) => break,
+            Ok(ChangeWait::Timeout) => {}
+            Ok(ChangeWait::Unsupported) => return EventWait::Unsupported,
+            Err(e) => return EventWait::Failed(e),
+        }
+    }
+
+    EventWait::Done
+}
+
+/// The source session when the source is the FunBox so both share one login,
+/// otherwise a session of its own, created on first use
+fn router_session<'a>(ip_source: &'a mut Box<dyn IpSource>, own_session: &'a mut Option<Session>, config: &Config,
+    logger: &mut Logger) -> Option<&'a mut Session>
+{
+    if let Some(session) = ip_source.router()
+    {
+        return Some(session);
+    }
+
+    let router = config.router.as_ref()?;
+
+    if own_session.is_none()
+    {
+        match Session::new(&router.address, &router.username, &router.password, router.session_cache())
+        {
+            Ok(session) => *own_session = Some(session),
+            Err(e) => {
+                logger.error(format!("Could not create FunBox session: {}", e).as_str());
+                return None;
+            }
+        }
+    }
+
+    own_session.as_mut()
+}
+
+/// Runs `[reconcile]` against the router
+fn reconcile_router(ip_source: &mut Box<dyn IpSource>, own_session: &mut Option<Session>, config: &Config, logger: &mut Logger)
+{
+    let Some(session) = router_session(ip_source, own_session, config, logger) else
+    {
+        return;
+    };
+
+    let report = reconcile::run(session, config, config.reconcile.dry_run);
+
+    for line in &report.changes
+    {
+        logger.info(line.as_str());
+    }
+    for line in &report.errors
+    {
+        logger.error(line.as_str());
+    }
+}
+
+/// Takes a device snapshot and logs the devices that joined or left since the previous one
+fn snapshot_devices(ip_source: &mut Box<dyn IpSource>, own_session: &mut Option<Session>, config: &Config,
+    updater: &mut Updater, logger: &mut Logger)
+{
+    let Some(session) = router_session(ip_source, own_session, config, logger) else
+    {
+        return;
+    };
+
+    match session.get_devices()
+    {
+        Ok(devices) => updater.record_devices(&devices, logger),
+        Err(e) => logger.error(format!("Could not list the LAN devices: {}", e).as_str()),
+    }
+}
+
+/// Leaves the loop cleanly: the state is saved after every update, so only the logger needs flushing
+fn shutdown(updater: &mut Updater, logger: &mut Logger) -> ExitCode
+{
+    // nothing is scheduled anymore
+    if let Err(e) = PollState::save(None, &updater.state_path)
+    {
+        logger.error(e.as_str());
+    }
+    logger.info("Received termination signal, exiting");
+    logger.flush();
+
+    ExitCode::from(EXIT_OK)
+}
+
+pub fn run(mut config: Config, config_path: &Path, mut logger: Logger) -> ExitCode
+{
+    let logger = &mut logger;
+
+    let signals = match Signals::register()
+    {
+        Ok(signals) => signals,
+        Err(e) => {
+            println!("Could not register signal handlers, exiting: {}", e);
+            logger.error(format!("Could not register signal handlers: {}", e).as_str());
+            return ExitCode::from(EXIT_CONFIG);
+        }
+    };
+
+    let mut state = load_state(&config, logger);
+    let mut discovery_poller = create_poller(&config);
+
+    // nothing cached yet and the router does not answer, e.g. it boots slower than this host after a power cut
+    while let Err(e) = resolve_router(&mut config, &mut state, logger)
+    {
+        let wait = discovery_poller.on_error();
+        logger.error(format!("{}; retrying in {}s", e, wait.as_secs()).as_str());
+
+        signals.sleep(wait);
+        if signals.should_terminate()
+        {
+            logger.info("Received termination signal, exiting");
+            logger.flush();
+            return ExitCode::from(EXIT_OK);
+        }
+    }
+
+    let mut ip_source = match source::create(&config)
+    {
+        Ok(src) => src,
+        Err(e) => {
+            println!("{}, exiting", e);
+            logger.error(e.as_str());
+            return ExitCode::from(EXIT_ROUTER);
+        }
+    };
+
+    let accounts = match create_accounts(&config)
+    {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            println!("{}, exiting", e);
+            logger.error(e.as_str());
+            return ExitCode::from(EXIT_UPDATE_FAILED);
+        },
+    };
+
+    logger.info(format!("Watching public IP using {}", ip_source.name()).as_str());
+
+    let mut updater = Updater::new(&config, accounts, state);
+
+    let mut events = if config.daemon.events { EventMode::Subscribed } else { EventMode::Polling };
+    let mut poller = create_poller(&config);
+
+    let mut own_session = None;
+    let mut next_reconcile = Instant::now();
+    let mut ipv6_prefix = None;
+    let mut next_snapshot = Instant::now();
+
+    loop
+    {
+        if signals.take_reload()
+        {
+            reload(&mut config, config_path, &mut updater, logger);
+            events = if config.daemon.events { EventMode::Subscribed } else { EventMode::Polling };
+            poller = create_poller(&config);
+            next_reconcile = Instant::now();
+            next_snapshot = Instant::now();
+        }
+
+        if reconcile::enabled(&config) && Instant::now() >= next_reconcile
+        {
+            reconcile_router(&mut ip_source, &mut own_session, &config, logger);
+            next_reconcile = Instant::now() + Duration::from_secs(config.reconcile.interval);
+        }
+
+        if config.devices.snapshot && Instant::now() >= next_snapshot
+        {
+            snapshot_devices(&mut ip_source, &mut own_session, &config, &mut updater, logger);
+            next_snapshot = Instant::now() + Duration::from_secs(config.devices.interval);
+        }
+
+        let checked_at = Instant::now();
+
+        let connectivity = match ip_source.connectivity()
+        {
+            Ok(connectivity) => connectivity,
+            Err(e) => {
+                logger.error(format!("Could not get connectivity state from {}: {}", ip_source.name(), e).as_str());
+                None
+            }
+        };
+
+        let (wait, detected) = if connectivity == Some(false)
+        {
+            // no point in hammering the providers, wait for the link to come back
+            updater.wan_down(logger);
+            (poller.on_error(), true)
+        }
+        else
+        {
+            if connectivity == Some(true)
+            {
+                updater.wan_up(logger);
+            }
+
+            match ip_source.detect()
+            {
+                Ok(mut detection) =>
+                {
+                    let prefix = detection.lan_prefix;
+                    if prefix.is_some() && prefix != ipv6_prefix
+                    {
+                        // the pinholes point into the old prefix, rewrite them now rather than at the next interval
+                        if ipv6_prefix.is_some() && config.reconcile.pinholes
+                        {
+                            logger.info("IPv6 prefix changed, updating the pinholes");
+                            reconcile_router(&mut ip_source, &mut own_session, &config, logger);
+                            next_reconcile = Instant::now() + Duration::from_secs(config.reconcile.interval);
+                        }
+                        ipv6_prefix = prefix;
+                    }
+
+                    let notes = policy::apply(&mut detection, &config.policy);
+                    // withheld addresses are not an error, the records are just left alone
+                    let withheld = !notes.is_empty();
+                    updater.report_policy(notes, logger);
+
+                    if withheld || updater.has_usable_address(&detection)
+                    {
+                        updater.handle(&detection, logger);
+                        (poller.on_detection(&detection), true)
+                    }
+                    else
+                    {
+                        let wait = poller.on_error();
+                        logger.error(format!("{} did not report any usable public IP, retrying in {}s", detection.source, wait.as_secs()).as_str());
+                        (wait, false)
+                    }
+                },
+                Err(e) =>
+                {
+                    let wait = poller.on_error();
+                    logger.error(format!("Could not get public IP from {}: {}; retrying in {}s", ip_source.name(), e, wait.as_secs()).as_str());
+                    (wait, false)
+                },
+            }
+        };
+
+        if signals.should_terminate()
+        {
+            return shutdown(&mut updater, logger);
+        }
+
+        if let EventMode::FailedAt(at) = events
+        {
+            if at.elapsed() >= EVENT_RESUBSCRIBE_DELAY
+            {
+                events = EventMode::Subscribed;
+            }
+        }
+
+        // the router tasks and the update retries only run between two waits, a wait must not overrun them
+        let next_task = [
+                reconcile::enabled(&config).then_some(next_reconcile),
+                config.devices.snapshot.then_some(next_snapshot),
+                updater.next_retry(),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|at| at.saturating_duration_since(Instant::now()))
+            // one still due did not get its turn (WAN down, no address), it must not make the loop spin
+            .filter(|wait| !wait.is_zero())
+            .min()
+            .unwrap_or(Duration::MAX);
+        let wait = wait.min(next_task);
+
+        if detected && events == EventMode::Subscribed
+        {
+            let timeout = Duration::from_secs(config.daemon.event_timeout).min(next_task);
+            poller.set_next(timeout);
+            updater.record_poll("events", timeout, &poller, logger);
+
+            match wait_for_event(&mut ip_source, &signals, timeout)
+            {
+                EventWait::Done => {
+                    // events come in bursts, checking on each one would load the router more than polling
+                    signals.sleep(Duration::from_secs(config.daemon.poll_interval).saturating_sub(checked_at.elapsed()));
+                    if signals.should_terminate()
+                    {
+                        return shutdown(&mut updater, logger);
+                    }
+                    continue;
+                }
+                EventWait::Unsupported => events = EventMode::Polling,
+                EventWait::Failed(e) => {
+                    logger.error(format!("Event subscription failed: {}; polling every {}s instead", e, config.daemon.poll_interval).as_str());
+                    events = EventMode::FailedAt(Instant::now());
+                }
+            }
+
+            poller.set_next(wait);
+        }
+
+        updater.record_poll("polling", wait, &poller, logger);
+
+        signals.sleep(wait);
+
+        if signals.should_terminate()
+        {
+            return shutdown(&mut updater, logger);
+        }
+    }
+}
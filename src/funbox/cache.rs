@@ -0,0 +1,60 @@
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderValue;
+
+/// Router login kept across restarts, so a restart does not cost a `createContext`
+/// (each one shows up in the router admin log and counts toward the lockout)
+#[derive(Serialize, Deserialize, Default)]
+pub struct SessionCache
+{
+    /// Router and user the context belongs to, a cache for another one is ignored
+    pub address: String,
+    pub username: String,
+    pub context_id: String,
+    pub cookie: String,
+    /// Recent login attempts, for the hourly cap
+    #[serde(default)]
+    pub login_attempts: Vec<DateTime<Utc>>,
+}
+
+impl SessionCache
+{
+    /// A missing, unreadable or corrupt cache is the same as no cache
+    pub fn load(path: &Path) -> Option<SessionCache>
+    {
+        let cache: SessionCache = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+
+        // both end up in request headers, which cannot hold control characters
+        let valid = HeaderValue::from_str(&cache.context_id).is_ok() && HeaderValue::from_str(&cache.cookie).is_ok();
+
+        valid.then_some(cache)
+    }
+
+    /// Writes the cache readable by the owner only, it grants admin access to the router
+    pub fn save(&self, path: &Path) -> Result<(), String>
+    {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir).map_err(|e| format!("Could not create '{}': {}", dir.display(), e))?;
+        }
+
+        let mut tmp_path = PathBuf::from(path);
+        tmp_path.as_mut_os_string().push(".tmp");
+
+        let json = serde_json::to_vec(self).map_err(|e| e.to_string())?;
+
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp_path)
+            .map_err(|e| format!("Could not create '{}': {}", tmp_path.display(), e))?;
+        // mode() only applies to new files, a leftover temporary file keeps its permissions
+        file.set_permissions(Permissions::from_mode(0o600))
+            .and_then(|_| file.write_all(&json))
+            .and_then(|_| file.sync_all())
+            .map_err(|e| format!("Could not write '{}': {}", tmp_path.display(), e))?;
+
+        fs::rename(&tmp_path, path).map_err(|e| format!("Could not replace '{}': {}", path.display(), e))
+    }
+}
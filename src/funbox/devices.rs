@@ -0,0 +1,58 @@
+use serde_json::{json, Value};
+
+use super::{string_field, SahError, Session};
+
+/// A LAN device as known by the router, connected or not
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Device
+{
+    /// Upper case, colon separated
+    pub mac: String,
+    /// Empty when the router never saw an IPv4 address for it
+    pub ip: String,
+    /// DHCP host name or the name given in the web UI
+    pub name: String,
+    /// e.g. `ETH1` or `wl0`
+    pub interface: String,
+    pub device_type: String,
+    pub active: bool,
+}
+
+impl Session
+{
+    /// Every device the router has seen on the LAN, sorted by MAC address
+    pub fn get_devices(&mut self) -> Result<Vec<Device>, SahError>
+    {
+        // `lan and not self` leaves out the router itself and its interfaces
+        let answer = self.call("Devices", "get", json!({ "expression": "lan and not self" }))?;
+
+        let Some(entries) = answer.get("status").and_then(Value::as_array) else
+        {
+            return Ok(vec![]);
+        };
+
+        let mut devices: Vec<Device> = entries.iter()
+            .filter_map(|device| {
+                let mac = device.get("PhysAddress")?.as_str().filter(|mac| !mac.is_empty())?;
+                let interface = match string_field(device, "Layer2Interface")
+                {
+                    interface if interface.is_empty() => string_field(device, "InterfaceName"),
+                    interface => interface,
+                };
+
+                Some(Device {
+                    mac: mac.to_ascii_uppercase(),
+                    ip: string_field(device, "IPAddress"),
+                    name: string_field(device, "Name"),
+                    interface,
+                    device_type: string_field(device, "DeviceType"),
+                    active: device.get("Active").and_then(Value::as_bool).unwrap_or(false),
+                })
+            })
+            .collect();
+
+        devices.sort_by(|a, b| a.mac.cmp(&b.mac));
+
+        Ok(devices)
+    }
+}
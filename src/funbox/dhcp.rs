@@ -0,0 +1,59 @@
+use std::net::Ipv4Addr;
+
+use serde_json::{json, Value};
+
+use super::{SahError, Session};
+
+/// The LAN DHCP pool
+const POOL: &str = "DHCPv4.Server.Pool.default";
+
+/// A fixed address reservation as listed by `getStaticLeases`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StaticLease
+{
+    /// Upper case, colon separated, as the router reports it
+    pub mac: String,
+    pub ip: String,
+}
+
+impl std::fmt::Display for StaticLease
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{} -> {}", self.mac, self.ip)
+    }
+}
+
+impl Session
+{
+    /// Every static lease of the LAN pool
+    pub fn get_static_leases(&mut self) -> Result<Vec<StaticLease>, SahError>
+    {
+        let answer = self.call(POOL, "getStaticLeases", json!({}))?;
+
+        let Some(leases) = answer.get("status").and_then(Value::as_array) else
+        {
+            return Ok(vec![]);
+        };
+
+        Ok(leases.iter().filter_map(|lease| Some(StaticLease {
+            mac: lease.get("MACAddress")?.as_str()?.to_ascii_uppercase(),
+            ip: lease.get("IPAddress").and_then(Value::as_str).unwrap_or_default().to_string(),
+        })).collect())
+    }
+
+    /// Reserves `ip` for `mac`; the router refuses a MAC or an address that already has a lease
+    pub fn add_static_lease(&mut self, mac: &str, ip: Ipv4Addr) -> Result<(), SahError>
+    {
+        self.call(POOL, "addStaticLease", json!({ "MACAddress": mac, "IPAddress": ip.to_string() }))?;
+
+        Ok(())
+    }
+
+    pub fn delete_static_lease(&mut self, mac: &str) -> Result<(), SahError>
+    {
+        self.call(POOL, "deleteStaticLease", json!({ "MACAddress": mac }))?;
+
+        Ok(())
+    }
+}
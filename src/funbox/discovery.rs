@@ -0,0 +1,160 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use reqwest::blocking::Client;
+
+use crate::config::RouterConfig;
+use crate::state::DiscoveredRouter;
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to collect SSDP answers
+const SSDP_WAIT: Duration = Duration::from_secs(3);
+const SSDP_ADDRESS: &str = "239.255.255.250:1900";
+const SSDP_SEARCH: &str = "M-SEARCH * HTTP/1.1\r\n\
+    HOST: 239.255.255.250:1900\r\n\
+    MAN: \"ssdp:discover\"\r\n\
+    MX: 2\r\n\
+    ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+
+/// Replaces `address = "auto"` in `[router]` by a discovered address: the cached one if it still
+/// speaks the Sah API, otherwise the default gateway, otherwise the first SSDP gateway that does.
+/// When nothing answers the cached address is kept, the router may just be rebooting.
+/// Returns a line for the log, `None` when the address is configured
+pub fn resolve(router: &mut RouterConfig, cache: &mut Option<DiscoveredRouter>) -> Result<Option<String>, String>
+{
+    if !router.is_auto()
+    {
+        return Ok(None);
+    }
+
+    if let Some(cached) = cache.as_ref()
+    {
+        if probe(&cached.address)
+        {
+            router.address = cached.address.clone();
+            return Ok(Some(format!("Using router {} found by {} on {}", cached.address, cached.method,
+                cached.discovered_at.format("%Y-%m-%d"))));
+        }
+    }
+
+    let (address, method) = match (discover(), cache.as_ref())
+    {
+        (Ok(found), _) => found,
+        (Err(e), Some(cached)) => {
+            router.address = cached.address.clone();
+            return Ok(Some(format!("{}; keeping router {} found by {} on {}", e, cached.address, cached.method,
+                cached.discovered_at.format("%Y-%m-%d"))));
+        }
+        (Err(e), None) => return Err(e),
+    };
+
+    router.address = address.clone();
+    *cache = Some(DiscoveredRouter { address: address.clone(), method: method.to_string(), discovered_at: Utc::now() });
+
+    Ok(Some(format!("Discovered router {} by {}", address, method)))
+}
+
+/// Looks for a router speaking the Sah API, returns its address and how it was found
+pub fn discover() -> Result<(String, &'static str), String>
+{
+    let gateway = default_gateway();
+
+    if let Some(gateway) = gateway
+    {
+        if probe(&gateway.to_string())
+        {
+            return Ok((gateway.to_string(), "default gateway"));
+        }
+    }
+
+    for candidate in ssdp_search().unwrap_or_default()
+    {
+        if Some(candidate) != gateway.map(IpAddr::V4) && probe(&candidate.to_string())
+        {
+            return Ok((candidate.to_string(), "SSDP"));
+        }
+    }
+
+    Err(match gateway
+    {
+        Some(gateway) => format!("Could not discover the router: the default gateway {} does not speak the Sah API and no SSDP gateway does", gateway),
+        None => "Could not discover the router: no default gateway and no SSDP gateway speaking the Sah API".to_string(),
+    })
+}
+
+/// Default IPv4 gateway with the lowest metric, from `/proc/net/route`
+pub fn default_gateway() -> Option<Ipv4Addr>
+{
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+
+    // Iface Destination Gateway Flags RefCnt Use Metric Mask ..., addresses in host byte order hex
+    routes.lines().skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let destination = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+            let gateway = u32::from_str_radix(fields.get(2)?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+            let metric = fields.get(6)?.parse::<u32>().ok()?;
+
+            // RTF_UP | RTF_GATEWAY
+            (destination == 0 && flags & 0x3 == 0x3).then(|| (metric, Ipv4Addr::from(gateway.to_ne_bytes())))
+        })
+        .min_by_key(|(metric, _)| *metric)
+        .map(|(_, gateway)| gateway)
+}
+
+/// Whether `address` answers an unauthenticated `/ws` call the way a Sah router does,
+/// i.e. with a JSON envelope (usually a permission error) rather than a web page
+pub fn probe(address: &str) -> bool
+{
+    let Ok(client) = Client::builder().timeout(PROBE_TIMEOUT).build() else
+    {
+        return false;
+    };
+
+    let resp = client.post(format!("http://{}/ws", address))
+        .header("Content-Type", "application/x-sah-ws-4-call+json")
+        .body(r#"{"service":"sah.Device.Information","method":"get","parameters":{}}"#)
+        .send();
+
+    let Ok(body) = resp.and_then(|r| r.bytes()) else
+    {
+        return false;
+    };
+
+    match serde_json::from_slice::<serde_json::Value>(&body)
+    {
+        Ok(serde_json::Value::Object(obj)) => ["status", "errors", "result"].iter().any(|key| obj.contains_key(*key)),
+        _ => false,
+    }
+}
+
+/// Addresses of the UPnP internet gateways answering an SSDP search
+fn ssdp_search() -> std::io::Result<BTreeSet<IpAddr>>
+{
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+    socket.send_to(SSDP_SEARCH.as_bytes(), SSDP_ADDRESS.parse::<SocketAddr>().map_err(std::io::Error::other)?)?;
+
+    let mut found = BTreeSet::new();
+    let mut buf = [0u8; 2048];
+    let end = Instant::now() + SSDP_WAIT;
+
+    while Instant::now() < end
+    {
+        match socket.recv_from(&mut buf)
+        {
+            Ok((len, from)) if buf[..len].starts_with(b"HTTP/1.1 200") => {
+                found.insert(from.ip());
+            }
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use super::packets::{SahErrorInfo, SahErrorKind};
+
+/// Failure of a call to the FunBox `/ws` API
+#[derive(Debug)]
+pub enum SahError
+{
+    /// The request could not be sent or the response not read
+    Transport(reqwest::Error),
+    /// Non-success HTTP status other than 401
+    HttpStatus(u16),
+    /// The router rejected the context (HTTP 401), even after logging in again
+    Unauthorized,
+    /// The router answered with an `errors` array, the first entry is kept
+    Protocol(SahErrorInfo),
+    /// The response body is not valid UTF-8
+    InvalidUtf8(std::str::Utf8Error),
+    /// The response is not the JSON we expect
+    Decode(serde_json::Error),
+    /// Login succeeded but the router returned no context ID
+    EmptyContext,
+    /// The context ID or the cookie the router returned cannot be sent back in a header
+    InvalidContext,
+    /// An earlier login failed, no attempt before the delay has passed
+    LoginBackoff { failures: u32, retry_in: Duration },
+    /// The router locked the account after too many failed logins
+    LockedOut { retry_in: Option<Duration> },
+    /// [`MAX_LOGINS_PER_HOUR`](super::MAX_LOGINS_PER_HOUR) reached, whatever the outcome of the logins
+    LoginLimit { attempts: usize, retry_in: Duration },
+    /// Login failed, the next attempt is allowed after `retry_in`
+    Login { retry_in: Duration, source: Box<SahError> },
+}
+
+impl SahError
+{
+    /// Whether the error comes from the credentials rather than from the router or the network
+    pub fn is_auth(&self) -> bool
+    {
+        match self
+        {
+            SahError::Unauthorized => true,
+            SahError::Login { source, .. } => source.is_auth(),
+            _ => false,
+        }
+    }
+
+    pub fn is_locked_out(&self) -> bool
+    {
+        match self
+        {
+            SahError::LockedOut { .. } => true,
+            SahError::Login { source, .. } => source.is_locked_out(),
+            _ => false,
+        }
+    }
+
+    /// Kind of the router error, `None` if the call did not fail with an error object
+    pub fn protocol_kind(&self) -> Option<SahErrorKind>
+    {
+        match self
+        {
+            SahError::Protocol(info) => Some(info.kind()),
+            _ => None,
+        }
+    }
+
+    /// What the user can do about the error, for the command line output
+    pub fn hint(&self) -> Option<&'static str>
+    {
+        if self.is_auth()
+        {
+            return Some("check the [router] username and password");
+        }
+        if self.is_locked_out()
+        {
+            return Some("wait for the router to unlock the account and check the [router] password, every failed login extends the lock");
+        }
+
+        match self.protocol_kind()?
+        {
+            SahErrorKind::PermissionDenied => Some("the router user is not allowed to call this method, use the admin account"),
+            SahErrorKind::NotFound | SahErrorKind::UnknownMethod => Some("this firmware does not provide the service or method"),
+            SahErrorKind::InvalidParameters => Some("the router rejected the parameters of the call"),
+            SahErrorKind::Other => None,
+        }
+    }
+}
+
+impl fmt::Display for SahError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            SahError::Transport(e) => write!(f, "request failed: {}", e),
+            SahError::HttpStatus(status) => write!(f, "HTTP status {}", status),
+            SahError::Unauthorized => write!(f, "access unauthorized (HTTP status 401)"),
+            SahError::Protocol(e) if e.info.is_empty() => write!(f, "router error {}: {}", e.error, e.description),
+            SahError::Protocol(e) => write!(f, "router error {}: {} ({})", e.error, e.description, e.info),
+            SahError::InvalidUtf8(e) => write!(f, "response is not valid UTF-8: {}", e),
+            SahError::Decode(e) => write!(f, "could not parse response: {}", e),
+            SahError::EmptyContext => write!(f, "response context ID is empty"),
+            SahError::InvalidContext => write!(f, "response context ID or cookie contains invalid characters"),
+            SahError::LoginBackoff { failures, retry_in } => {
+                write!(f, "router login failed {} times, next attempt in {}s", failures, retry_in.as_secs() + 1)
+            }
+            SahError::LockedOut { retry_in: Some(retry_in) } => {
+                write!(f, "the router locked the account after too many failed logins, unlocked in {}s", retry_in.as_secs())
+            }
+            SahError::LockedOut { retry_in: None } => write!(f, "the router locked the account after too many failed logins"),
+            SahError::LoginLimit { attempts, retry_in } => {
+                write!(f, "{} router logins in the last hour, next attempt allowed in {}s", attempts, retry_in.as_secs() + 1)
+            }
+            SahError::Login { retry_in, source } => write!(f, "could not login: {}; next attempt in {}s", source, retry_in.as_secs()),
+        }
+    }
+}
+
+impl Error for SahError
+{
+    fn source(&self) -> Option<&(dyn Error + 'static)>
+    {
+        match self
+        {
+            SahError::Transport(e) => Some(e),
+            SahError::InvalidUtf8(e) => Some(e),
+            SahError::Decode(e) => Some(e),
+            SahError::Login { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SahError
+{
+    fn from(e: reqwest::Error) -> SahError
+    {
+        SahError::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for SahError
+{
+    fn from(e: serde_json::Error) -> SahError
+    {
+        SahError::Decode(e)
+    }
+}
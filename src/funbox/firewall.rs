@@ -0,0 +1,199 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use serde_json::{json, Value};
+
+use super::{string_field, SahError, Session};
+
+/// Origin of the rules we create; `webui` rules show up (and can be edited) in the router web UI
+pub const ORIGIN: &str = "webui";
+
+/// Transport protocol of a NAT rule
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol
+{
+    #[default]
+    Tcp,
+    Udp,
+    Both,
+}
+
+impl Protocol
+{
+    /// IP protocol numbers as the Firewall service wants them
+    pub fn code(&self) -> &'static str
+    {
+        match self
+        {
+            Protocol::Tcp => "6",
+            Protocol::Udp => "17",
+            Protocol::Both => "6,17",
+        }
+    }
+
+    /// Name of a protocol code, the code itself if unknown
+    pub fn name(code: &str) -> &str
+    {
+        match code
+        {
+            "6" => "tcp",
+            "17" => "udp",
+            "6,17" | "17,6" => "tcp+udp",
+            _ => code,
+        }
+    }
+}
+
+/// A NAT rule as listed by `Firewall.getPortForwarding`; ports are strings since the router
+/// also accepts ranges such as `8000-8010`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PortForward
+{
+    /// Full id, prefixed by the origin, e.g. `webui_dynhost_https`
+    pub id: String,
+    pub description: String,
+    /// Protocol codes, see [`Protocol::code`]
+    pub protocol: String,
+    pub external_port: String,
+    pub internal_port: String,
+    pub destination: String,
+    pub enabled: bool,
+}
+
+impl std::fmt::Display for PortForward
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{} {} {} -> {}:{}{}", self.id, Protocol::name(&self.protocol), self.external_port, self.destination,
+            self.internal_port, if self.enabled { "" } else { " (disabled)" })
+    }
+}
+
+/// An IPv6 firewall opening as listed by `Firewall.getPinhole`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Pinhole
+{
+    /// Full id, prefixed by the origin
+    pub id: String,
+    pub description: String,
+    /// Protocol codes, see [`Protocol::code`]
+    pub protocol: String,
+    pub port: String,
+    pub destination: String,
+    pub enabled: bool,
+}
+
+impl std::fmt::Display for Pinhole
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        write!(f, "{} {} [{}]:{}{}", self.id, Protocol::name(&self.protocol), self.destination, self.port,
+            if self.enabled { "" } else { " (disabled)" })
+    }
+}
+
+impl Session
+{
+    /// Every IPv4 NAT rule of the router, whatever its origin
+    pub fn get_port_forwards(&mut self) -> Result<Vec<PortForward>, SahError>
+    {
+        let answer = self.call("Firewall", "getPortForwarding", json!({}))?;
+
+        // `status` maps each rule id to the rule
+        let Some(rules) = answer.get("status").and_then(Value::as_object) else
+        {
+            return Ok(vec![]);
+        };
+
+        Ok(rules.iter().map(|(id, rule)| PortForward {
+            id: rule.get("Id").and_then(Value::as_str).unwrap_or(id).to_string(),
+            description: string_field(rule, "Description"),
+            protocol: string_field(rule, "Protocol"),
+            external_port: string_field(rule, "ExternalPort"),
+            internal_port: string_field(rule, "InternalPort"),
+            destination: string_field(rule, "DestinationIPAddress"),
+            enabled: rule.get("Enable").and_then(Value::as_bool).unwrap_or(false),
+        }).collect())
+    }
+
+    /// Creates the rule, or replaces the rule with the same `id` (given without the origin prefix);
+    /// returns the full id the router gave it
+    pub fn set_port_forward(&mut self, id: &str, description: &str, protocol: Protocol, external_port: u16,
+        destination: Ipv4Addr, internal_port: u16) -> Result<String, SahError>
+    {
+        let answer = self.call("Firewall", "setPortForwarding", json!({
+            "id": id,
+            "origin": ORIGIN,
+            "sourceInterface": "data",
+            "description": description,
+            "protocol": protocol.code(),
+            "externalPort": external_port.to_string(),
+            "internalPort": internal_port.to_string(),
+            "destinationIPAddress": destination.to_string(),
+            "sourcePrefix": "",
+            "enable": true,
+            "persistent": true,
+        }))?;
+
+        Ok(answer.get("status").and_then(Value::as_str).unwrap_or(id).to_string())
+    }
+
+    /// Deletes the rule with the full `id` as listed by [`Session::get_port_forwards`]
+    pub fn delete_port_forward(&mut self, id: &str) -> Result<(), SahError>
+    {
+        self.call("Firewall", "deletePortForwarding", json!({ "id": id, "origin": ORIGIN }))?;
+
+        Ok(())
+    }
+
+    /// Every IPv6 pinhole of the router, whatever its origin
+    pub fn get_pinholes(&mut self) -> Result<Vec<Pinhole>, SahError>
+    {
+        let answer = self.call("Firewall", "getPinhole", json!({}))?;
+
+        let Some(rules) = answer.get("status").and_then(Value::as_object) else
+        {
+            return Ok(vec![]);
+        };
+
+        Ok(rules.iter().map(|(id, rule)| Pinhole {
+            id: rule.get("Id").and_then(Value::as_str).unwrap_or(id).to_string(),
+            description: string_field(rule, "Description"),
+            protocol: string_field(rule, "Protocol"),
+            port: string_field(rule, "DestinationPort"),
+            destination: string_field(rule, "DestinationIPAddress"),
+            enabled: rule.get("Enable").and_then(Value::as_bool).unwrap_or(false),
+        }).collect())
+    }
+
+    /// Opens `port` of `destination` from the internet, or replaces the pinhole with the same
+    /// `id` (given without the origin prefix); returns the full id the router gave it
+    pub fn set_pinhole(&mut self, id: &str, description: &str, protocol: Protocol, port: u16, destination: Ipv6Addr)
+        -> Result<String, SahError>
+    {
+        let answer = self.call("Firewall", "setPinhole", json!({
+            "id": id,
+            "origin": ORIGIN,
+            "sourceInterface": "data",
+            "description": description,
+            "protocol": protocol.code(),
+            "sourcePort": "",
+            "sourcePrefix": "",
+            "destinationPort": port.to_string(),
+            "destinationIPAddress": destination.to_string(),
+            "ipversion": 6,
+            "enable": true,
+            "persistent": true,
+        }))?;
+
+        Ok(answer.get("status").and_then(Value::as_str).unwrap_or(id).to_string())
+    }
+
+    /// Deletes the pinhole with the full `id` as listed by [`Session::get_pinholes`]
+    pub fn delete_pinhole(&mut self, id: &str) -> Result<(), SahError>
+    {
+        self.call("Firewall", "deletePinhole", json!({ "id": id, "origin": ORIGIN }))?;
+
+        Ok(())
+    }
+}
@@ -1,13 +1,26 @@
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
 
 use reqwest::{blocking::Client, header::{HeaderMap, HeaderValue}};
+use serde::Deserialize;
+use serde_json::json;
 use serde::de::DeserializeOwned;
 
-use crate::ROUTER_ADMIN_PASSWORD;
+use self::packets::{EventRequest, EventResponse, LoginResponse, SahEnvelope, SahEvent, SahRequest, StateResponse, WANStatusResponse};
+
+pub use self::error::SahError;
 
-use self::packets::{LoginResponse, LoginRequest, StateRequest, StateResponse, WANStatusRequest, WANStatusResponse};
+use self::cache::SessionCache;
 
-mod packets;
+mod cache;
+pub mod devices;
+pub mod dhcp;
+pub mod discovery;
+mod error;
+pub mod firewall;
+pub mod packets;
 
 struct SahClient
 {
@@ -16,77 +29,276 @@ struct SahClient
 
 impl SahClient
 {
-    pub fn new() -> Result<SahClient, String>
+    pub fn new() -> Result<SahClient, SahError>
     {
         let mut def_headers = HeaderMap::new();
         def_headers.insert("Content-Type", HeaderValue::from_static("application/x-sah-ws-4-call+json"));
 
-        let mut client = Client::builder()
+        let client = Client::builder()
             .deflate(true)
             .gzip(true)
             .brotli(true)
             .use_native_tls()
             .default_headers(def_headers)
             .http1_only()
-            .build()
-            .map_err(|e| e.to_string())?;
+            .build()?;
 
         Ok(SahClient { http_client: client })
     }
 }
 
+/// Delay before the first login retry, doubled on every further failure
+const LOGIN_BACKOFF_BASE: Duration = Duration::from_secs(5);
+const LOGIN_BACKOFF_MAX: Duration = Duration::from_secs(300);
+/// Hard cap on logins, whatever the backoff says; the firmware locks the admin account
+/// (web UI included) after a handful of failures
+const MAX_LOGINS_PER_HOUR: usize = 10;
+/// Data fields carrying the remaining lock time in seconds, firmwares differ
+const LOCK_TIME_FIELDS: [&str; 3] = ["remainingLockTime", "waitTime", "lockTime"];
+
+enum SessionState
+{
+    /// No context yet, or the router rejected the last one
+    LoggedOut,
+    /// Context restored from the cache file, not validated yet
+    Cached,
+    Authenticated,
+    /// Login failed (e.g. the router is rebooting), no attempt before `until`
+    Backoff { until: Instant, failures: u32 },
+}
+
 pub struct Session
 {
     client: SahClient,
     ip: String,
+    username: String,
+    password: String,
     context_id: String,
     cookie: String,
+    state: SessionState,
+    /// Event channel, `None` until subscribed
+    channel_id: Option<u64>,
+    cache_path: Option<PathBuf>,
+    /// Login attempts within the last hour, kept in the cache so restarts do not reset the cap
+    login_attempts: Vec<DateTime<Utc>>,
 }
 
 impl Session
 {
-    pub fn connect(ip: &str) -> Result<Session, String>
+    /// Creates a session which logs in on the first call, unless `cache_path` holds
+    /// a context of the same router and user that is still valid
+    pub fn new(ip: &str, username: &str, password: &str, cache_path: Option<&Path>) -> Result<Session, SahError>
     {
         let mut sess = Session {
             client: SahClient::new()?,
             ip: ip.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
             context_id: String::new(),
             cookie: String::new(),
+            state: SessionState::LoggedOut,
+            channel_id: None,
+            cache_path: cache_path.map(Path::to_path_buf),
+            login_attempts: vec![],
         };
 
-        sess.login().map_err(|e| format!("Could not login: {}", match e {
-            Ok(msg) => msg,
-            Err(_) => "Session timed out (HTTP status 401)".to_string(),
-        }))?;
+        if let Some(cache) = cache_path.and_then(SessionCache::load)
+        {
+            sess.login_attempts = cache.login_attempts;
+
+            if cache.address == ip && cache.username == username && !cache.context_id.is_empty()
+            {
+                sess.context_id = cache.context_id;
+                sess.cookie = cache.cookie;
+                sess.state = SessionState::Cached;
+            }
+        }
 
         Ok(sess)
     }
 
-    pub fn get_public_ip(&self) -> Result<String, Result<String, ()>>
+    /// Creates a session and logs in right away (or validates the cached context)
+    pub fn connect(ip: &str, username: &str, password: &str, cache_path: Option<&Path>) -> Result<Session, SahError>
     {
-        let resp = self.init_authorized_post()
-        .body(serde_json::to_string(&WANStatusRequest::create()).map_err(|e| e.to_string()).map_err(|e| Ok(e))?)
-        .send()
-        .map_err(|e| e.to_string()).map_err(|e| Ok(e))?;
+        let mut sess = Session::new(ip, username, password, cache_path)?;
 
-        let resp: WANStatusResponse = self.parse_response(resp)?;
+        sess.ensure_logged_in()?;
 
-        Ok(resp.data.IPAddress)
+        Ok(sess)
     }
 
-    fn is_internet(&self) -> Result<bool, Result<String, ()>>
+    pub fn address(&self) -> &str
     {
-        let resp = self.init_authorized_post()
-        .body(serde_json::to_string(&StateRequest::create()).map_err(|e| e.to_string()).map_err(|e| Ok(e))?)
-        .send()
-        .map_err(|e| e.to_string()).map_err(|e| Ok(e))?;
+        &self.ip
+    }
+
+    /// Calls `service.method` with the named arguments in `params` and returns the whole answer,
+    /// i.e. `status` and, for methods with output parameters, `data`
+    pub fn call(&mut self, service: &str, method: &str, params: serde_json::Value) -> Result<serde_json::Value, SahError>
+    {
+        self.request(&SahRequest::create(service, method, params))
+    }
+
+    /// Same as [`Session::call`], decoding the answer into `T`
+    pub fn call_typed<T>(&mut self, service: &str, method: &str, params: serde_json::Value) -> Result<T, SahError>
+        where T: DeserializeOwned
+    {
+        self.request(&SahRequest::create(service, method, params))
+    }
+
+    pub fn get_wan_status(&mut self) -> Result<WANStatusResponse, SahError>
+    {
+        self.call_typed("NMC", "getWANStatus", json!({}))
+    }
+
+    /// Whether the router considers the internet connection up
+    pub fn is_internet(&mut self) -> Result<bool, SahError>
+    {
+        let resp: StateResponse = self.call_typed("UserInterface", "getState", json!({}))?;
 
-        let resp: StateResponse = self.parse_response(resp)?;
-        
         Ok(resp.status.as_str() == "connected")
     }
 
-    pub fn login(&mut self) -> Result<(), Result<String, ()>>
+    /// Long-polls the event channel for `handlers`, subscribing on the first call; returns the
+    /// events received within `timeout`, which is empty if nothing happened
+    pub fn wait_events(&mut self, handlers: &[&str], timeout: Duration) -> Result<Vec<SahEvent>, SahError>
+    {
+        self.ensure_logged_in()?;
+
+        let body = serde_json::to_string(&EventRequest::create(handlers, self.channel_id.unwrap_or(0)))?;
+
+        let resp = match self.init_authorized_post()
+            .header("Content-Type", "application/x-sah-event-4-call+json")
+            .timeout(timeout)
+            .body(body)
+            .send()
+        {
+            Ok(resp) => resp,
+            Err(e) if e.is_timeout() => return Ok(vec![]),
+            Err(e) => return Err(SahError::Transport(e)),
+        };
+
+        // the channel belongs to the context, a new login needs a new subscription
+        if resp.status().as_u16() == 401
+        {
+            self.state = SessionState::LoggedOut;
+            self.channel_id = None;
+        }
+
+        let resp: EventResponse = self.parse_response(resp)?;
+        self.channel_id = Some(resp.channelid);
+
+        Ok(resp.events.into_iter().map(|e| e.data).collect())
+    }
+
+    /// Sends an authorized request; if the router rejects the context (HTTP 401)
+    /// logs in again and retries the request once
+    fn request<Resp>(&mut self, request: &SahRequest) -> Result<Resp, SahError>
+        where Resp: DeserializeOwned
+    {
+        let body = serde_json::to_string(request)?;
+
+        self.ensure_logged_in()?;
+
+        match self.send_authorized(&body)
+        {
+            Err(SahError::Unauthorized) => {
+                self.state = SessionState::LoggedOut;
+                self.channel_id = None;
+                self.ensure_logged_in()?;
+
+                let resp = self.send_authorized(&body);
+                if let Err(SahError::Unauthorized) = resp
+                {
+                    self.state = SessionState::LoggedOut;
+                }
+                resp
+            }
+            resp => resp,
+        }
+    }
+
+    fn send_authorized<T>(&self, body: &str) -> Result<T, SahError>
+        where T: DeserializeOwned
+    {
+        let resp = self.init_authorized_post()
+        .body(body.to_string())
+        .send()?;
+
+        self.parse_response(resp)
+    }
+
+    /// Logs in unless already authenticated; after a failed login further attempts
+    /// are refused until the backoff delay has passed, so a rebooting router is not hammered
+    fn ensure_logged_in(&mut self) -> Result<(), SahError>
+    {
+        let failures = match self.state
+        {
+            SessionState::Authenticated => return Ok(()),
+            SessionState::Cached => {
+                // a cheap call tells whether the router still knows the context
+                match self.send_authorized::<StateResponse>(&serde_json::to_string(&SahRequest::create("UserInterface", "getState", json!({})))?)
+                {
+                    Ok(_) => {
+                        self.state = SessionState::Authenticated;
+                        return Ok(());
+                    }
+                    Err(SahError::Unauthorized) => {
+                        self.state = SessionState::LoggedOut;
+                        0
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            SessionState::LoggedOut => 0,
+            SessionState::Backoff { until, failures } => {
+                let now = Instant::now();
+                if now < until
+                {
+                    return Err(SahError::LoginBackoff { failures, retry_in: until - now });
+                }
+                failures
+            }
+        };
+
+        let hour_ago = Utc::now() - chrono::Duration::hours(1);
+        self.login_attempts.retain(|at| *at > hour_ago);
+
+        if self.login_attempts.len() >= MAX_LOGINS_PER_HOUR
+        {
+            let retry_in = (self.login_attempts[0] - hour_ago).to_std().unwrap_or_default();
+            return Err(SahError::LoginLimit { attempts: self.login_attempts.len(), retry_in });
+        }
+
+        self.login_attempts.push(Utc::now());
+
+        let result = self.login();
+        self.save_cache();
+
+        match result
+        {
+            Ok(()) => {
+                self.state = SessionState::Authenticated;
+                Ok(())
+            }
+            Err(e) => {
+                let failures = failures.saturating_add(1);
+                let mut delay = LOGIN_BACKOFF_BASE.saturating_mul(1u32 << failures.saturating_sub(1).min(16)).min(LOGIN_BACKOFF_MAX);
+
+                // trying again before the router unlocks the account only extends the lock
+                if let SahError::LockedOut { retry_in: Some(lock) } = &e
+                {
+                    delay = delay.max(*lock);
+                }
+
+                self.state = SessionState::Backoff { until: Instant::now() + delay, failures };
+
+                Err(SahError::Login { retry_in: delay, source: Box::new(e) })
+            }
+        }
+    }
+
+    fn login(&mut self) -> Result<(), SahError>
     {
         let mut headers = HeaderMap::new();
         headers.insert("Authorization", HeaderValue::from_static("X-Sah-Login"));
@@ -94,16 +306,14 @@ impl Session
         let resp = self.client.http_client
         .post(format!("http://{}/ws", self.ip.clone()))
         .headers(headers)
-        .body(serde_json::to_string(&LoginRequest::create("admin".to_string(), ROUTER_ADMIN_PASSWORD.to_string())).map_err(|e| Ok(e.to_string()))?)
-        .send()
-        .map_err(|e| Ok(e.to_string()))?;
+        .body(serde_json::to_string(&SahRequest::login(&self.username, &self.password))?)
+        .send()?;
 
-        resp.headers().get_all("set-cookie").iter().for_each(|elm| 
+        resp.headers().get_all("set-cookie").iter().filter_map(|elm| elm.to_str().ok()).for_each(|value|
             {
-                let value = unsafe{ String::from_utf8_unchecked(elm.as_bytes().to_vec()) };
                 if value.contains("HttpOnly")
                 {
-                    if let Some(cookie_value) = value.split(";").next()
+                    if let Some(cookie_value) = value.split(';').next()
                     {
                         self.cookie = cookie_value.to_string();
                     }
@@ -111,12 +321,33 @@ impl Session
             }
         );
 
-        let resp: LoginResponse = self.parse_response(resp)?;
+        // only a failed login tells about a locked account, other calls are not affected by the lock
+        if resp.status().as_u16() == 429
+        {
+            let retry_in = resp.headers().get("retry-after")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+
+            return Err(SahError::LockedOut { retry_in });
+        }
+
+        let value = read_body(resp)?;
+        if let Some(locked_out) = lockout(&value)
+        {
+            return Err(locked_out);
+        }
+
+        let resp: LoginResponse = decode(value)?;
         let ctx_id = resp.data.contextID;
 
         if ctx_id.is_empty()
         {
-            Err(Ok("Response context ID is empty".to_string()))
+            Err(SahError::EmptyContext)
+        }
+        else if HeaderValue::from_str(&format!("X-Sah {}", ctx_id)).is_err() || HeaderValue::from_str(&self.cookie).is_err()
+        {
+            Err(SahError::InvalidContext)
         }
         else
         {
@@ -125,6 +356,25 @@ impl Session
         }
     }
 
+    /// Saves the context and the login attempts; a cache that cannot be written only costs
+    /// a login on the next start, so errors are ignored
+    fn save_cache(&self)
+    {
+        if let Some(path) = &self.cache_path
+        {
+            let cache = SessionCache {
+                address: self.ip.clone(),
+                username: self.username.clone(),
+                context_id: self.context_id.clone(),
+                cookie: self.cookie.clone(),
+                login_attempts: self.login_attempts.clone(),
+            };
+
+            cache.save(path).ok();
+        }
+    }
+
+    /// The context and cookie are checked by `login` and by the cache, the headers cannot fail
     fn init_authorized_post(&self) -> reqwest::blocking::RequestBuilder
     {
         let mut headers = HeaderMap::new();
@@ -138,27 +388,70 @@ impl Session
         .headers(headers)
     }
 
-    /// Returns Ok(None) if Unauthorized Access
-    fn parse_response<T>(&self, resp: reqwest::blocking::Response) -> Result<T, Result<String, ()>>
+    /// Checks the envelope before decoding the answer: HTTP 401 is [`SahError::Unauthorized`]
+    /// and a non-empty `errors` array is [`SahError::Protocol`]
+    fn parse_response<T>(&self, resp: reqwest::blocking::Response) -> Result<T, SahError>
         where T: DeserializeOwned
     {
-        // check for errors
+        decode(read_body(resp)?)
+    }
+}
 
-        if resp.status().as_u16() == 401
-        {
-            return Err(Err(()));
-        }
+/// A field of a listed router object as a string; firmwares send some numbers (ports,
+/// protocols) either way, a missing field is empty
+fn string_field(object: &serde_json::Value, field: &str) -> String
+{
+    match object.get(field)
+    {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Number(n)) => n.to_string(),
+        _ => String::new(),
+    }
+}
 
-        let bytes = resp.bytes().map_err(|e| Ok(e.to_string()))?;
-        let body = unsafe{ String::from_utf8_unchecked(bytes.to_vec()) };
+/// The JSON body of a successful answer
+fn read_body(resp: reqwest::blocking::Response) -> Result<serde_json::Value, SahError>
+{
+    let status = resp.status();
 
-        if body.contains("errors")
-        {
-            Err(Ok(format!("Got some errors in response: {}", body)))
-        }
-        else
-        {
-            serde_json::from_slice(body.as_bytes()).map_err(|e| Ok(format!("Could not parse response: {}", e.to_string())))
-        }
+    if status.as_u16() == 401
+    {
+        return Err(SahError::Unauthorized);
+    }
+    if !status.is_success()
+    {
+        return Err(SahError::HttpStatus(status.as_u16()));
+    }
+
+    let bytes = resp.bytes()?;
+    let body = std::str::from_utf8(&bytes).map_err(SahError::InvalidUtf8)?;
+
+    Ok(serde_json::from_str(body)?)
+}
+
+fn decode<T>(value: serde_json::Value) -> Result<T, SahError>
+    where T: DeserializeOwned
+{
+    let envelope = SahEnvelope::deserialize(&value)?;
+    if let Some(err) = envelope.errors.into_iter().next()
+    {
+        return Err(SahError::Protocol(err));
     }
+
+    Ok(T::deserialize(value)?)
+}
+
+/// The lock error of a failed login: a lock time in the data, or a description about
+/// the account being locked ("blocked" and the like are not)
+fn lockout(value: &serde_json::Value) -> Option<SahError>
+{
+    let envelope = SahEnvelope::deserialize(value).ok()?;
+    let err = envelope.errors.first()?;
+
+    let lock_time = LOCK_TIME_FIELDS.iter().find_map(|field| envelope.data.get(*field)?.as_u64());
+    let about_lock = err.description.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .any(|word| word.starts_with("lock"));
+
+    (lock_time.is_some() || about_lock).then(|| SahError::LockedOut { retry_in: lock_time.map(Duration::from_secs) })
 }
\ No newline at end of file
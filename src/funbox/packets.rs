@@ -1,19 +1,112 @@
+#![allow(non_snake_case, dead_code)]
+
+use std::net::{IpAddr, Ipv6Addr};
+
 use serde::{Serialize, Deserialize};
 
+use crate::util::{parse_ipv6_prefix, with_interface_id};
+
+/// Fields every `/ws` answer may carry: `status` is the return value of the method, `data` its
+/// output parameters and `errors` is only present when the call failed, e.g.
+/// `{"status":null,"errors":[{"error":13,"description":"Permission denied","info":"NMC"}]}`
+#[derive(Deserialize)]
+pub struct SahEnvelope
+{
+    #[serde(default)]
+    pub status: serde_json::Value,
+    #[serde(default)]
+    pub data: serde_json::Value,
+    #[serde(default)]
+    pub errors: Vec<SahErrorInfo>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SahErrorInfo
+{
+    pub error: i64,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub info: String,
+}
+
+/// Error codes callers may want to react to
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SahErrorKind
+{
+    /// The user has no right to call the method, e.g. not logged in as `admin`
+    PermissionDenied,
+    /// The service, object or parameter does not exist on this firmware
+    NotFound,
+    /// The service exists but has no such method
+    UnknownMethod,
+    /// Missing or malformed parameters
+    InvalidParameters,
+    Other,
+}
+
+impl SahErrorInfo
+{
+    /// Classifies by code where the firmware codes are known, by description otherwise,
+    /// since the description strings are more stable across firmwares than the codes
+    pub fn kind(&self) -> SahErrorKind
+    {
+        match self.error
+        {
+            13 => return SahErrorKind::PermissionDenied,
+            196618 => return SahErrorKind::NotFound,
+            _ => {}
+        }
+
+        let description = self.description.to_lowercase();
+
+        if description.contains("permission denied")
+        {
+            SahErrorKind::PermissionDenied
+        }
+        else if description.contains("function not found") || description.contains("method not found")
+        {
+            SahErrorKind::UnknownMethod
+        }
+        else if description.contains("not found")
+        {
+            SahErrorKind::NotFound
+        }
+        else if description.contains("parameter") || description.contains("argument")
+        {
+            SahErrorKind::InvalidParameters
+        }
+        else
+        {
+            SahErrorKind::Other
+        }
+    }
+}
+
+/// Any `/ws` call, `parameters` is an object of named arguments
 #[derive(Serialize)]
-pub struct LoginRequest
+pub struct SahRequest
 {
-    pub method: String,
-    pub parameters: LoginParameters,
     pub service: String,
+    pub method: String,
+    pub parameters: serde_json::Value,
 }
 
-#[derive(Serialize)]
-pub struct LoginParameters
+impl SahRequest
 {
-    pub applicationName: String,
-    pub password: String,
-    pub username: String,
+    pub fn create(service: &str, method: &str, parameters: serde_json::Value) -> SahRequest
+    {
+        SahRequest { service: service.to_string(), method: method.to_string(), parameters }
+    }
+
+    pub fn login(username: &str, password: &str) -> SahRequest
+    {
+        SahRequest::create("sah.Device.Information", "createContext", serde_json::json!({
+            "applicationName": "webui",
+            "username": username,
+            "password": password,
+        }))
+    }
 }
 
 #[derive(Deserialize)]
@@ -31,83 +124,293 @@ pub struct LoginData
     pub groups: String,
 }
 
-impl LoginRequest
+#[derive(Deserialize)]
+pub struct StateResponse
 {
-    pub fn create(username: String, password: String) -> LoginRequest
-    {
-        LoginRequest { 
-            method: "createContext".to_string(), 
-            parameters: LoginParameters { 
-                applicationName: "webui".to_string(),
-                password, 
-                username 
-            }, 
-            service: "sah.Device.Information".to_string()
-        }
-    }
+    pub status: String,
 }
 
-#[derive(Serialize)]
-pub struct StateRequest
+/// Physical uplink reported in `LinkType`
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub enum LinkType
 {
-    pub method: String,
-    pub parameters: NoParameters,
-    pub service: String,
+    /// Fibre, `gpon`
+    Gpon,
+    Vdsl,
+    Adsl,
+    /// Ethernet uplink, e.g. an external ONT or an SFP module
+    Ethernet,
+    /// 4G backup, the address is usually behind the mobile operator CGNAT
+    Mobile,
+    /// Empty while the link is down
+    #[default]
+    None,
+    Other(String),
 }
 
-#[derive(Serialize)]
-pub struct NoParameters;
-
-#[derive(Deserialize)]
-pub struct StateResponse
+impl LinkType
 {
-    pub status: String,
+    pub fn is_mobile(&self) -> bool
+    {
+        *self == LinkType::Mobile
+    }
 }
 
-impl StateRequest
+impl From<String> for LinkType
 {
-    pub fn create() -> StateRequest
+    fn from(value: String) -> LinkType
     {
-        StateRequest { method: "getState".to_string(), parameters: NoParameters{}, service: "UserInterface".to_string() }
+        match value.to_lowercase().as_str()
+        {
+            "gpon" | "ftth" => LinkType::Gpon,
+            "vdsl" | "vdsl2" => LinkType::Vdsl,
+            "dsl" | "adsl" | "adsl2" => LinkType::Adsl,
+            "ethernet" | "eth" | "sfp" => LinkType::Ethernet,
+            "mobile" | "lte" | "4g" | "5g" | "wwan" | "usb" => LinkType::Mobile,
+            "" => LinkType::None,
+            _ => LinkType::Other(value),
+        }
     }
 }
 
-#[derive(Serialize)]
-pub struct WANStatusRequest
+impl std::fmt::Display for LinkType
 {
-    pub method: String,
-    pub parameters: NoParameters,
-    pub service: String,
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            LinkType::Gpon => write!(f, "gpon"),
+            LinkType::Vdsl => write!(f, "vdsl"),
+            LinkType::Adsl => write!(f, "dsl"),
+            LinkType::Ethernet => write!(f, "ethernet"),
+            LinkType::Mobile => write!(f, "mobile"),
+            LinkType::None => write!(f, "none"),
+            LinkType::Other(value) => write!(f, "{}", value),
+        }
+    }
 }
 
-#[derive(Deserialize)]
-pub struct FTTH
+/// Answer of `NMC.getWANStatus`; the set of fields depends on the uplink (fibre, xDSL, 4G backup)
+/// and the firmware, so everything is optional and unknown fields are kept for `router-info`
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct WanStatus
 {
+    #[serde(deserialize_with = "null_string")]
     pub WanState: String,
-    pub LinkType: String,
+    #[serde(with = "link_type")]
+    pub LinkType: LinkType,
+    #[serde(deserialize_with = "null_string")]
     pub LinkState: String,
-    pub GponState: String,
+    /// Fibre only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub GponState: Option<String>,
+    #[serde(deserialize_with = "null_string")]
     pub MACAddress: String,
+    #[serde(deserialize_with = "null_string")]
     pub Protocol: String,
+    #[serde(deserialize_with = "null_string")]
     pub ConnectionState: String,
+    #[serde(deserialize_with = "null_string")]
     pub LastConnectionError: String,
-    pub IPAddress: String,
+    /// `None` while the WAN has no address
+    #[serde(with = "wan_address")]
+    pub IPAddress: Option<IpAddr>,
+    #[serde(deserialize_with = "null_string")]
     pub RemoteGateway: String,
+    #[serde(deserialize_with = "null_string")]
     pub DNSServers: String,
-    pub IPv6Address: String,
+    #[serde(with = "wan_address")]
+    pub IPv6Address: Option<IpAddr>,
+    /// Prefix delegated to the LAN, e.g. `2a01:cb00:1:200::/56`, empty without IPv6
+    #[serde(deserialize_with = "null_string")]
+    pub IPv6DelegatedPrefix: String,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
 }
 
-#[derive(Deserialize)]
+impl WanStatus
+{
+    /// The /64 the LAN hosts get their addresses from: the first one of the delegated prefix,
+    /// or the one of the WAN address on firmwares that do not report it; `None` without IPv6
+    pub fn lan_prefix(&self) -> Option<Ipv6Addr>
+    {
+        let prefix = match (parse_ipv6_prefix(&self.IPv6DelegatedPrefix), self.IPv6Address)
+        {
+            (Some((prefix, _)), _) => prefix,
+            (None, Some(IpAddr::V6(addr))) => addr,
+            _ => return None,
+        };
+
+        Some(with_interface_id(prefix, Ipv6Addr::UNSPECIFIED))
+    }
+}
+
+/// Depending on the uplink, firmwares send `null` instead of an empty string for fields that
+/// do not apply, e.g. everything xDSL-related on the 4G backup
+fn null_string<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error>
+{
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+mod link_type
+{
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::LinkType;
+
+    pub fn serialize<S: Serializer>(value: &LinkType, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        serializer.collect_str(value)
+    }
+
+    /// Some firmwares send `null` while the link is down
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LinkType, D::Error>
+    {
+        Ok(Option::<String>::deserialize(deserializer)?.map(LinkType::from).unwrap_or_default())
+    }
+}
+
+/// Addresses are sent as strings, empty when missing
+mod wan_address
+{
+    use std::net::IpAddr;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    use crate::util::parse_ip;
+
+    pub fn serialize<S: Serializer>(value: &Option<IpAddr>, serializer: S) -> Result<S::Ok, S::Error>
+    {
+        match value
+        {
+            Some(ip) => serializer.collect_str(ip),
+            None => serializer.serialize_str(""),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<IpAddr>, D::Error>
+    {
+        let value = Option::<String>::deserialize(deserializer)?.unwrap_or_default();
+
+        if value.trim().is_empty()
+        {
+            return Ok(None);
+        }
+
+        parse_ip(&value).map(Some).ok_or_else(|| D::Error::custom(format!("invalid WAN address '{}'", value)))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
 pub struct WANStatusResponse
 {
     pub status: bool,
-    pub data: FTTH
+    #[serde(default)]
+    pub data: WanStatus,
+}
+
+/// Subscribes to (channelid 0) or long-polls (channelid from the previous answer) the event channel
+#[derive(Serialize)]
+pub struct EventRequest
+{
+    pub events: Vec<String>,
+    pub channelid: u64,
+}
+
+#[derive(Deserialize)]
+pub struct EventResponse
+{
+    pub channelid: u64,
+    #[serde(default)]
+    pub events: Vec<EventEnvelope>,
 }
 
-impl WANStatusRequest
+#[derive(Deserialize)]
+pub struct EventEnvelope
 {
-    pub fn create() -> WANStatusRequest
+    pub data: SahEvent,
+}
+
+#[derive(Deserialize)]
+pub struct SahEvent
+{
+    pub handler: String,
+    #[serde(default)]
+    pub object: serde_json::Value,
+}
+
+impl EventRequest
+{
+    pub fn create(events: &[&str], channelid: u64) -> EventRequest
+    {
+        EventRequest { events: events.iter().map(|e| e.to_string()).collect(), channelid }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn parse(json: &str) -> WanStatus
     {
-        WANStatusRequest { method: "getWANStatus".to_string(), parameters: NoParameters{}, service: "NMC".to_string() }
+        serde_json::from_str::<WANStatusResponse>(json).unwrap().data
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn wan_status_ftth()
+    {
+        let wan = parse(r#"{"status":true,"data":{"WanState":"up","LinkType":"gpon","LinkState":"up",
+            "GponState":"O5_Operation","MACAddress":"AA:BB:CC:DD:EE:FF","Protocol":"dhcp",
+            "ConnectionState":"Bound","LastConnectionError":"None","IPAddress":"90.1.2.3",
+            "RemoteGateway":"90.1.2.1","DNSServers":"80.10.246.2,81.253.149.6",
+            "IPv6Address":"2a01:cb00:1:200::1","IPv6DelegatedPrefix":"2a01:cb00:1:200::/56"}}"#);
+
+        assert_eq!(wan.LinkType, LinkType::Gpon);
+        assert_eq!(wan.GponState.as_deref(), Some("O5_Operation"));
+        assert_eq!(wan.IPAddress, Some("90.1.2.3".parse().unwrap()));
+        assert_eq!(wan.lan_prefix(), Some("2a01:cb00:1:200::".parse().unwrap()));
+    }
+
+    #[test]
+    fn wan_status_vdsl()
+    {
+        let wan = parse(r#"{"status":true,"data":{"WanState":"up","LinkType":"vdsl","LinkState":"up",
+            "MACAddress":"AA:BB:CC:DD:EE:FF","Protocol":"ppp","ConnectionState":"Connected",
+            "LastConnectionError":"ERROR_NONE","IPAddress":"86.1.2.3","RemoteGateway":"193.253.160.3",
+            "DNSServers":"80.10.246.2","IPv6Address":"","IPv6DelegatedPrefix":""}}"#);
+
+        assert_eq!(wan.LinkType, LinkType::Vdsl);
+        assert_eq!(wan.GponState, None);
+        assert_eq!(wan.IPAddress, Some("86.1.2.3".parse().unwrap()));
+        assert_eq!(wan.IPv6Address, None);
+        assert_eq!(wan.lan_prefix(), None);
+    }
+
+    #[test]
+    fn wan_status_lte_backup_with_nulls()
+    {
+        let wan = parse(r#"{"status":true,"data":{"WanState":"up","LinkType":"lte","LinkState":null,
+            "GponState":null,"MACAddress":null,"Protocol":null,"ConnectionState":null,
+            "LastConnectionError":null,"IPAddress":"10.170.1.2","RemoteGateway":null,"DNSServers":null,
+            "IPv6Address":null,"IPv6DelegatedPrefix":null}}"#);
+
+        assert!(wan.LinkType.is_mobile());
+        assert_eq!(wan.LinkState, "");
+        assert_eq!(wan.ConnectionState, "");
+        assert_eq!(wan.IPAddress, Some("10.170.1.2".parse().unwrap()));
+        assert_eq!(wan.lan_prefix(), None);
+    }
+
+    #[test]
+    fn wan_status_link_down()
+    {
+        let wan = parse(r#"{"status":true,"data":{"WanState":null,"LinkType":null,"LinkState":null,
+            "IPAddress":null,"IPv6Address":null}}"#);
+
+        assert_eq!(wan.LinkType, LinkType::None);
+        assert_eq!(wan.IPAddress, None);
+    }
+}
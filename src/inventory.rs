@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+
+use chrono::Utc;
+
+use crate::funbox::devices::Device;
+use crate::state::SeenDevice;
+
+fn describe(mac: &str, name: &str, ip: &str) -> String
+{
+    match (name.is_empty(), ip.is_empty())
+    {
+        (true, true) => mac.to_string(),
+        (true, false) => format!("{} ({})", mac, ip),
+        (false, true) => format!("{} {}", mac, name),
+        (false, false) => format!("{} {} ({})", mac, name, ip),
+    }
+}
+
+/// Updates the known devices with a snapshot and returns a log line per device that joined
+/// or left since the previous one; devices never seen before are called out as new
+pub fn compare(known: &mut BTreeMap<String, SeenDevice>, devices: &[Device]) -> Vec<String>
+{
+    let now = Utc::now();
+    // the first snapshot only records the LAN, every device would show up as new
+    let first = known.is_empty();
+    let mut lines = vec![];
+
+    for device in devices
+    {
+        match known.get_mut(&device.mac)
+        {
+            None => {
+                if !first && device.active
+                {
+                    lines.push(format!("New device {} on {}", describe(&device.mac, &device.name, &device.ip), device.interface));
+                }
+                known.insert(device.mac.clone(), SeenDevice {
+                    name: device.name.clone(),
+                    ip: device.ip.clone(),
+                    active: device.active,
+                    first_seen: now,
+                    last_seen: now,
+                });
+            }
+            Some(seen) => {
+                if device.active && !seen.active
+                {
+                    lines.push(format!("Device joined: {} on {}", describe(&device.mac, &device.name, &device.ip), device.interface));
+                }
+                else if !device.active && seen.active
+                {
+                    lines.push(format!("Device left: {}", describe(&device.mac, &seen.name, &seen.ip)));
+                }
+
+                seen.active = device.active;
+                if !device.name.is_empty()
+                {
+                    seen.name = device.name.clone();
+                }
+                if !device.ip.is_empty()
+                {
+                    seen.ip = device.ip.clone();
+                }
+            }
+        }
+
+        if device.active
+        {
+            known.entry(device.mac.clone()).and_modify(|seen| seen.last_seen = now);
+        }
+    }
+
+    // the router forgets devices after a while, treat those as gone
+    for (mac, seen) in known.iter_mut().filter(|(mac, seen)| seen.active && !devices.iter().any(|d| &d.mac == *mac))
+    {
+        lines.push(format!("Device left: {}", describe(mac, &seen.name, &seen.ip)));
+        seen.active = false;
+    }
+
+    if first
+    {
+        lines.push(format!("Recorded {} LAN devices, {} connected", devices.len(), devices.iter().filter(|d| d.active).count()));
+    }
+
+    lines
+}
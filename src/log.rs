@@ -1,7 +1,6 @@
 use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::time::{SystemTime, UNIX_EPOCH};
-use chrono::{DateTime, Local, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono::Local;
 
 pub struct Logger
 {
@@ -19,25 +18,23 @@ impl Logger
 
     fn current_time(&self) -> String
     {
-        let off: i32 = Local.timestamp_nanos(0).offset().fix().local_minus_utc();
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let timestamp = timestamp + (off as u64);
-
-        let ndt = NaiveDateTime::from_timestamp_opt(timestamp as i64, 0).unwrap();
-        let dt: DateTime<Utc> = DateTime::from_utc(ndt, Utc);
-
-        let date = dt.format("%Y-%m-%d %H:%M:%S");
+        Local::now().format("%Y-%m-%d %H:%M:%S").to_string()
+    }
 
-        date.to_string()
+    /// Makes sure everything logged so far reached the disk
+    pub fn flush(&mut self)
+    {
+        self.file.flush().ok();
+        self.file.sync_data().ok();
     }
 
     pub fn info(&mut self, msg: &str)
     {
-        self.file.write_all(format!("[{}][INFO] {}\n", self.current_time(), msg).as_bytes());
+        self.file.write_all(format!("[{}][INFO] {}\n", self.current_time(), msg).as_bytes()).ok();
     }
 
     pub fn error(&mut self, msg: &str)
     {
-        self.file.write_all(format!("[{}][ERROR] {}\n", self.current_time(), msg).as_bytes());
+        self.file.write_all(format!("[{}][ERROR] {}\n", self.current_time(), msg).as_bytes()).ok();
     }
 }
\ No newline at end of file
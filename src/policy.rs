@@ -0,0 +1,60 @@
+use crate::config::{PolicyConfig, UnreachablePolicy};
+use crate::source::Detection;
+use crate::util::classify;
+
+/// Applies the `[policy]` section to the detected addresses, dropping or replacing those that
+/// cannot be reached from the internet; returns one note per such address telling why and what was done
+pub fn apply(detection: &mut Detection, policy: &PolicyConfig) -> Vec<String>
+{
+    // the mobile operator NATs the 4G backup, even an address that looks public is not reachable
+    let backup = detection.link.as_ref().map(|link| link.is_mobile()).unwrap_or(false);
+
+    let mut notes = vec![];
+    let mut addresses = vec![];
+
+    for ip in detection.addresses.drain(..)
+    {
+        let class = classify(&ip);
+
+        let reason = if !class.is_global()
+        {
+            class.description()
+        }
+        else if backup
+        {
+            "address of the 4G backup link"
+        }
+        else
+        {
+            addresses.push(ip);
+            continue;
+        };
+
+        match policy.unreachable
+        {
+            UnreachablePolicy::Skip => {
+                notes.push(format!("{} is not reachable from the internet ({}), not publishing it", ip, reason));
+                detection.withheld.push(ip);
+            }
+            UnreachablePolicy::Publish => {
+                notes.push(format!("{} is not reachable from the internet ({}), publishing it anyway", ip, reason));
+                addresses.push(ip);
+            }
+            UnreachablePolicy::Fallback => match policy.fallback_for(&ip)
+            {
+                Some(fallback) => {
+                    notes.push(format!("{} is not reachable from the internet ({}), publishing fallback {} instead", ip, reason, fallback));
+                    addresses.push(fallback);
+                }
+                None => {
+                    notes.push(format!("{} is not reachable from the internet ({}) and there is no fallback for its family, not publishing it", ip, reason));
+                    detection.withheld.push(ip);
+                }
+            },
+        }
+    }
+
+    detection.addresses = addresses;
+
+    notes
+}
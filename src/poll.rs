@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::source::Detection;
+
+/// Picks the delay before the next poll of a source that cannot push changes: starts at
+/// `base`, doubles while the address stays the same up to `max`, and drops back to `base`
+/// as soon as the address or the link state changes or the source fails
+pub struct PollScheduler
+{
+    base: Duration,
+    max: Duration,
+    current: Duration,
+    /// Addresses and link details of the last detection
+    last_seen: Option<String>,
+    errors: u32,
+    next_poll: Option<DateTime<Utc>>,
+}
+
+impl PollScheduler
+{
+    pub fn new(base: Duration, max: Duration) -> PollScheduler
+    {
+        PollScheduler { base, max, current: base, last_seen: None, errors: 0, next_poll: None }
+    }
+
+    /// Delay after a successful detection
+    pub fn on_detection(&mut self, detection: &Detection) -> Duration
+    {
+        let seen = format!("{:?} {:?}", detection.addresses, detection.details);
+        self.errors = 0;
+
+        if self.last_seen.as_ref() == Some(&seen)
+        {
+            self.current = self.current.saturating_mul(2).min(self.max);
+        }
+        else
+        {
+            self.current = self.base;
+            self.last_seen = Some(seen);
+        }
+
+        self.schedule()
+    }
+
+    /// Delay after a failed detection: back to `base`, then doubling while the failures go on
+    pub fn on_error(&mut self) -> Duration
+    {
+        if self.errors == 0
+        {
+            self.current = self.base;
+        }
+        else
+        {
+            self.current = self.current.saturating_mul(2).min(self.max);
+        }
+
+        self.errors = self.errors.saturating_add(1);
+        self.last_seen = None;
+
+        self.schedule()
+    }
+
+    /// Delay chosen by something else, e.g. the event timeout; only recorded for the status output
+    pub fn set_next(&mut self, delay: Duration)
+    {
+        self.next_poll = chrono::Duration::from_std(delay).ok().map(|d| Utc::now() + d);
+    }
+
+    pub fn next_poll(&self) -> Option<DateTime<Utc>>
+    {
+        self.next_poll
+    }
+
+    fn schedule(&mut self) -> Duration
+    {
+        self.set_next(self.current);
+        self.current
+    }
+}
@@ -0,0 +1,293 @@
+use std::net::IpAddr;
+
+use reqwest::blocking::Client;
+
+use crate::retry::FailureKind;
+use crate::util::parse_ip;
+
+use super::{DnsProvider, UpdateError, UpdateStatus};
+
+/// Any registrar speaking the dyndns2 update protocol
+pub struct Dyndns2Client
+{
+    http_client: Client,
+    /// Update endpoint, e.g. `https://members.dyndns.org/nic/update`
+    server: String,
+    username: String,
+    password: String,
+}
+
+impl Dyndns2Client
+{
+    pub fn new(http_client: Client, server: String, username: String, password: String) -> Dyndns2Client
+    {
+        Dyndns2Client { http_client, server, username, password }
+    }
+
+    pub fn send_update(&self, domain: &str, ip: IpAddr) -> Result<UpdateStatus, UpdateError>
+    {
+        let response = self.http_client
+            .get(self.server.as_str())
+            .query(&[("hostname", domain), ("myip", ip.to_string().as_str())])
+            .basic_auth(self.username.as_str(), Some(self.password.as_str()))
+            .send()
+            .map_err(UpdateError::Transport)?;
+
+        let status = response.status();
+        if !status.is_success()
+        {
+            return Err(UpdateError::HttpStatus(status.as_u16()));
+        }
+
+        let text = response.text().map_err(UpdateError::Transport)?;
+
+        Dyndns2Response::parse(&text).into_result(ip)
+    }
+}
+
+/// Reply to a dyndns2 update, see <https://help.dyn.com/remote-access-api/return-codes/>
+#[derive(PartialEq, Eq, Debug)]
+pub enum Dyndns2Response
+{
+    /// `good [ip]` - the record was updated
+    Good(Option<IpAddr>),
+    /// `nochg [ip]` - the record already had this address
+    NoChange(Option<IpAddr>),
+    /// `badauth` - wrong username or password
+    BadAuth,
+    /// `notfqdn` - the host name is not a fully qualified domain name
+    NotFqdn,
+    /// `nohost` - the host name does not exist or does not belong to this account
+    NoHost,
+    /// `numhost` - too many hosts in one request
+    NumHost,
+    /// `abuse` - the host is blocked for sending too many updates
+    Abuse,
+    /// `badagent` - the user agent is blocked or the request is malformed
+    BadAgent,
+    /// `dnserr` - the provider failed to apply the update
+    DnsErr,
+    /// `911` - the provider has a problem or is under maintenance
+    ServerError,
+    Unknown(String),
+}
+
+/// What to do with an account after a given response
+#[derive(PartialEq, Eq)]
+pub enum ResponseAction
+{
+    Done,
+    /// Retrying is pointless until the config changes
+    Disable,
+    /// The provider asked us to go away for a while
+    LongBackoff,
+    RetrySoon,
+}
+
+impl Dyndns2Response
+{
+    /// Parses the first line of the body; the address after `good`/`nochg` is optional,
+    /// some providers do not echo it
+    pub fn parse(body: &str) -> Dyndns2Response
+    {
+        let line = body.lines().next().unwrap_or("").trim();
+        let mut words = line.split_whitespace();
+        let code = words.next().unwrap_or("");
+        let echoed_ip = words.next().and_then(parse_ip);
+
+        match code
+        {
+            "good" => Dyndns2Response::Good(echoed_ip),
+            "nochg" => Dyndns2Response::NoChange(echoed_ip),
+            "badauth" => Dyndns2Response::BadAuth,
+            "notfqdn" => Dyndns2Response::NotFqdn,
+            "nohost" => Dyndns2Response::NoHost,
+            "numhost" => Dyndns2Response::NumHost,
+            "abuse" => Dyndns2Response::Abuse,
+            "badagent" => Dyndns2Response::BadAgent,
+            "dnserr" => Dyndns2Response::DnsErr,
+            "911" => Dyndns2Response::ServerError,
+            _ => Dyndns2Response::Unknown(line.to_string()),
+        }
+    }
+
+    pub fn action(&self) -> ResponseAction
+    {
+        match self
+        {
+            Dyndns2Response::Good(_) | Dyndns2Response::NoChange(_) => ResponseAction::Done,
+            Dyndns2Response::BadAuth
+            | Dyndns2Response::NotFqdn
+            | Dyndns2Response::NoHost
+            | Dyndns2Response::NumHost
+            | Dyndns2Response::BadAgent => ResponseAction::Disable,
+            // the protocol asks clients to wait at least 30 minutes after a 911, see retry::THROTTLED_MIN_DELAY
+            Dyndns2Response::Abuse | Dyndns2Response::ServerError => ResponseAction::LongBackoff,
+            Dyndns2Response::DnsErr | Dyndns2Response::Unknown(_) => ResponseAction::RetrySoon,
+        }
+    }
+
+    /// The response code as sent by the provider
+    pub fn code(&self) -> String
+    {
+        match self
+        {
+            Dyndns2Response::Good(_) => "good".to_string(),
+            Dyndns2Response::NoChange(_) => "nochg".to_string(),
+            Dyndns2Response::BadAuth => "badauth".to_string(),
+            Dyndns2Response::NotFqdn => "notfqdn".to_string(),
+            Dyndns2Response::NoHost => "nohost".to_string(),
+            Dyndns2Response::NumHost => "numhost".to_string(),
+            Dyndns2Response::Abuse => "abuse".to_string(),
+            Dyndns2Response::BadAgent => "badagent".to_string(),
+            Dyndns2Response::DnsErr => "dnserr".to_string(),
+            Dyndns2Response::ServerError => "911".to_string(),
+            Dyndns2Response::Unknown(text) => text.clone(),
+        }
+    }
+
+    pub fn description(&self) -> String
+    {
+        match self
+        {
+            Dyndns2Response::Good(_) => "updated".to_string(),
+            Dyndns2Response::NoChange(_) => "already up to date".to_string(),
+            Dyndns2Response::BadAuth => "badauth: wrong username or password".to_string(),
+            Dyndns2Response::NotFqdn => "notfqdn: not a fully qualified domain name".to_string(),
+            Dyndns2Response::NoHost => "nohost: the host does not exist in this account".to_string(),
+            Dyndns2Response::NumHost => "numhost: too many hosts in one request".to_string(),
+            Dyndns2Response::Abuse => "abuse: the host is blocked for too many updates".to_string(),
+            Dyndns2Response::BadAgent => "badagent: the client was rejected".to_string(),
+            Dyndns2Response::DnsErr => "dnserr: the provider failed to apply the update".to_string(),
+            Dyndns2Response::ServerError => "911: the provider has a problem".to_string(),
+            Dyndns2Response::Unknown(text) => format!("unexpected response '{}'", text),
+        }
+    }
+
+    /// Maps the response to an update result, checking that the provider echoed the address we sent
+    pub fn into_result(self, sent_ip: IpAddr) -> Result<UpdateStatus, UpdateError>
+    {
+        match self
+        {
+            Dyndns2Response::Good(Some(echoed)) | Dyndns2Response::NoChange(Some(echoed)) if echoed != sent_ip => {
+                Err(UpdateError::AddressMismatch { sent: sent_ip, echoed })
+            }
+            Dyndns2Response::Good(_) => Ok(UpdateStatus::Good),
+            Dyndns2Response::NoChange(_) => Ok(UpdateStatus::NoChange),
+            response => {
+                let kind = match response.action()
+                {
+                    ResponseAction::Disable => FailureKind::Permanent,
+                    ResponseAction::LongBackoff => FailureKind::Throttled,
+                    ResponseAction::RetrySoon | ResponseAction::Done => FailureKind::Transient,
+                };
+                Err(UpdateError::Rejected { code: response.code(), description: response.description(), kind })
+            }
+        }
+    }
+}
+
+impl DnsProvider for Dyndns2Client
+{
+    fn name(&self) -> &'static str
+    {
+        "dyndns2"
+    }
+
+    fn update(&self, domain: &str, ip: IpAddr) -> Result<UpdateStatus, UpdateError>
+    {
+        self.send_update(domain, ip)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr
+    {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_success_codes_with_and_without_address()
+    {
+        assert_eq!(Dyndns2Response::parse("good 1.2.3.4\n"), Dyndns2Response::Good(Some(ip("1.2.3.4"))));
+        assert_eq!(Dyndns2Response::parse("good"), Dyndns2Response::Good(None));
+        assert_eq!(Dyndns2Response::parse("nochg 2001:db8::1"), Dyndns2Response::NoChange(Some(ip("2001:db8::1"))));
+        assert_eq!(Dyndns2Response::parse("  nochg  "), Dyndns2Response::NoChange(None));
+    }
+
+    #[test]
+    fn parses_every_error_code()
+    {
+        let cases = [
+            ("badauth", Dyndns2Response::BadAuth, ResponseAction::Disable),
+            ("notfqdn", Dyndns2Response::NotFqdn, ResponseAction::Disable),
+            ("nohost", Dyndns2Response::NoHost, ResponseAction::Disable),
+            ("numhost", Dyndns2Response::NumHost, ResponseAction::Disable),
+            ("badagent", Dyndns2Response::BadAgent, ResponseAction::Disable),
+            ("abuse", Dyndns2Response::Abuse, ResponseAction::LongBackoff),
+            ("911", Dyndns2Response::ServerError, ResponseAction::LongBackoff),
+            ("dnserr", Dyndns2Response::DnsErr, ResponseAction::RetrySoon),
+        ];
+
+        for (body, response, action) in cases
+        {
+            let parsed = Dyndns2Response::parse(body);
+            assert_eq!(parsed.code(), body);
+            assert!(parsed.action() == action, "{}", body);
+            assert_eq!(parsed, response);
+        }
+    }
+
+    #[test]
+    fn only_the_first_line_counts()
+    {
+        assert_eq!(Dyndns2Response::parse("nohost\ngood 1.2.3.4"), Dyndns2Response::NoHost);
+        assert_eq!(Dyndns2Response::parse("<html>oops</html>"), Dyndns2Response::Unknown("<html>oops</html>".to_string()));
+        assert_eq!(Dyndns2Response::parse(""), Dyndns2Response::Unknown(String::new()));
+    }
+
+    #[test]
+    fn success_without_echoed_address_is_accepted()
+    {
+        assert!(matches!(Dyndns2Response::Good(None).into_result(ip("1.2.3.4")), Ok(UpdateStatus::Good)));
+        assert!(matches!(Dyndns2Response::NoChange(None).into_result(ip("1.2.3.4")), Ok(UpdateStatus::NoChange)));
+    }
+
+    #[test]
+    fn echoed_address_must_match()
+    {
+        assert!(matches!(Dyndns2Response::Good(Some(ip("1.2.3.4"))).into_result(ip("1.2.3.4")), Ok(UpdateStatus::Good)));
+        // the v4-mapped echo is the same address once normalised
+        assert!(matches!(Dyndns2Response::parse("nochg ::ffff:1.2.3.4").into_result(ip("1.2.3.4")), Ok(UpdateStatus::NoChange)));
+
+        match Dyndns2Response::Good(Some(ip("5.6.7.8"))).into_result(ip("1.2.3.4"))
+        {
+            Err(UpdateError::AddressMismatch { sent, echoed }) => {
+                assert_eq!(sent, ip("1.2.3.4"));
+                assert_eq!(echoed, ip("5.6.7.8"));
+            }
+            _ => panic!("expected an address mismatch"),
+        }
+    }
+
+    #[test]
+    fn errors_carry_the_failure_kind()
+    {
+        let kind = |body: &str| match Dyndns2Response::parse(body).into_result(ip("1.2.3.4"))
+        {
+            Err(UpdateError::Rejected { kind, .. }) => kind,
+            _ => panic!("expected {} to be rejected", body),
+        };
+
+        assert_eq!(kind("badauth"), FailureKind::Permanent);
+        assert_eq!(kind("nohost"), FailureKind::Permanent);
+        assert_eq!(kind("abuse"), FailureKind::Throttled);
+        assert_eq!(kind("911"), FailureKind::Throttled);
+        assert_eq!(kind("dnserr"), FailureKind::Transient);
+        assert_eq!(kind("whatever"), FailureKind::Transient);
+    }
+}
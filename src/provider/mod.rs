@@ -0,0 +1,174 @@
+use std::net::IpAddr;
+
+use reqwest::blocking::Client;
+
+use crate::config::{AccountConfig, ProviderKind, RecordType};
+use crate::log::Logger;
+use crate::retry::FailureKind;
+
+use self::dyndns2::Dyndns2Client;
+use self::ovh::OVHClient;
+
+mod dyndns2;
+mod ovh;
+
+pub enum UpdateStatus
+{
+    /// The record now points to the new address
+    Good,
+    /// The record already pointed to the new address
+    NoChange,
+}
+
+#[derive(Debug)]
+pub enum UpdateError
+{
+    /// The request could not be sent or the response not read
+    Transport(reqwest::Error),
+    /// Non-success HTTP status
+    HttpStatus(u16),
+    /// The provider answered with an error code, e.g. `badauth` or `911`
+    Rejected { code: String, description: String, kind: FailureKind },
+    /// The provider confirmed another address than the one we sent
+    AddressMismatch { sent: IpAddr, echoed: IpAddr },
+}
+
+impl UpdateError
+{
+    pub fn kind(&self) -> FailureKind
+    {
+        match self
+        {
+            UpdateError::Transport(_) | UpdateError::AddressMismatch { .. } => FailureKind::Transient,
+            UpdateError::HttpStatus(429) => FailureKind::Throttled,
+            // 401/403/404 will keep failing until the credentials or the host name are fixed
+            UpdateError::HttpStatus(status) if (400..500).contains(status) => FailureKind::Permanent,
+            UpdateError::HttpStatus(_) => FailureKind::Transient,
+            UpdateError::Rejected { kind, .. } => *kind,
+        }
+    }
+}
+
+impl std::fmt::Display for UpdateError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            UpdateError::Transport(e) => write!(f, "request failed: {}", e),
+            UpdateError::HttpStatus(status) => write!(f, "HTTP status {}", status),
+            UpdateError::Rejected { description, .. } => write!(f, "{}", description),
+            UpdateError::AddressMismatch { sent, echoed } => write!(f, "sent {} but the provider answered with {}", sent, echoed),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            UpdateError::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of publishing one address to one record
+pub struct UpdateReport
+{
+    pub domain: String,
+    pub provider: &'static str,
+    pub ip: IpAddr,
+    pub result: Result<UpdateStatus, UpdateError>,
+}
+
+/// A service able to point a DNS record at an address
+pub trait DnsProvider
+{
+    fn name(&self) -> &'static str;
+
+    fn update(&self, domain: &str, ip: IpAddr) -> Result<UpdateStatus, UpdateError>;
+}
+
+/// A configured record together with the provider publishing it
+pub struct Account
+{
+    pub domain: String,
+    pub records: Vec<RecordType>,
+    provider: Box<dyn DnsProvider>,
+}
+
+impl Account
+{
+    pub fn update(&self, ip: IpAddr) -> UpdateReport
+    {
+        UpdateReport {
+            domain: self.domain.clone(),
+            provider: self.provider.name(),
+            ip,
+            result: self.provider.update(&self.domain, ip),
+        }
+    }
+}
+
+impl UpdateReport
+{
+    /// Short form of the provider answer, as kept in the state file
+    pub fn response(&self) -> String
+    {
+        match &self.result
+        {
+            Ok(UpdateStatus::Good) => format!("good {}", self.ip),
+            Ok(UpdateStatus::NoChange) => format!("nochg {}", self.ip),
+            Err(UpdateError::Rejected { code, .. }) => code.clone(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    pub fn log(&self, logger: &mut Logger)
+    {
+        match &self.result
+        {
+            Ok(UpdateStatus::Good) => {
+                logger.info(format!("Updated {} to {} ({})", self.domain, self.ip, self.provider).as_str());
+            }
+            Ok(UpdateStatus::NoChange) => {
+                logger.info(format!("{} is already mapped to {} ({})", self.domain, self.ip, self.provider).as_str());
+            }
+            Err(e) => {
+                logger.error(format!("Encountered error while tried to update {} ({}): {}", self.domain, self.provider, e).as_str());
+            }
+        }
+    }
+}
+
+pub fn create_http_client() -> Result<Client, String>
+{
+    Client::builder()
+        .deflate(true)
+        .gzip(true)
+        .brotli(true)
+        .use_native_tls()
+        .user_agent(concat!("dynhost-client/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Creates one [`Account`] per `[[account]]` config section
+pub fn create_accounts(accounts: &[AccountConfig]) -> Result<Vec<Account>, String>
+{
+    let http_client = create_http_client()?;
+
+    Ok(accounts.iter().map(|acc| {
+        let provider: Box<dyn DnsProvider> = match acc.provider
+        {
+            ProviderKind::Ovh => Box::new(OVHClient::new(http_client.clone(), acc.username.clone(), acc.password.clone())),
+            ProviderKind::Dyndns2 => Box::new(Dyndns2Client::new(http_client.clone(),
+                acc.server.clone().unwrap_or_default(), acc.username.clone(), acc.password.clone())),
+        };
+
+        Account { domain: acc.domain.clone(), records: acc.records.clone(), provider }
+    }).collect())
+}
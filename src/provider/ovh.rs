@@ -0,0 +1,37 @@
+use std::net::IpAddr;
+
+use reqwest::blocking::Client;
+
+use super::dyndns2::Dyndns2Client;
+use super::{DnsProvider, UpdateError, UpdateStatus};
+
+const OVH_UPDATE_URL: &str = "https://www.ovh.com/nic/update?system=dyndns";
+
+/// OVH DynHost, a dyndns2 endpoint with per-record credentials
+pub struct OVHClient
+{
+    client: Dyndns2Client,
+}
+
+impl OVHClient
+{
+    pub fn new(http_client: Client, username: String, password: String) -> OVHClient
+    {
+        OVHClient {
+            client: Dyndns2Client::new(http_client, OVH_UPDATE_URL.to_string(), username, password)
+        }
+    }
+}
+
+impl DnsProvider for OVHClient
+{
+    fn name(&self) -> &'static str
+    {
+        "ovh"
+    }
+
+    fn update(&self, domain: &str, ip: IpAddr) -> Result<UpdateStatus, UpdateError>
+    {
+        self.client.send_update(domain, ip)
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::config::Config;
+use crate::funbox::{SahError, Session};
+use crate::funbox::firewall::{Pinhole, PortForward, ORIGIN};
+
+mod pinhole;
+mod port_forward;
+mod static_lease;
+
+/// Router entries created by us start their id with this (after the origin), anything else is left alone
+const MANAGED_PREFIX: &str = "dynhost_";
+
+/// The managed part of a router id, e.g. `dynhost_https` for `webui_dynhost_https`;
+/// `None` for entries we did not create, even if their id contains `dynhost_` further on
+fn managed_key(id: &str) -> Option<String>
+{
+    id.strip_prefix(ORIGIN)?
+        .strip_prefix('_')
+        .filter(|key| key.starts_with(MANAGED_PREFIX))
+        .map(str::to_string)
+}
+
+/// A router entry carrying an id we can mark as ours
+trait RouterEntry
+{
+    fn id(&self) -> &str;
+    fn set_id(&mut self, id: String);
+}
+
+impl RouterEntry for PortForward
+{
+    fn id(&self) -> &str { &self.id }
+    fn set_id(&mut self, id: String) { self.id = id; }
+}
+
+impl RouterEntry for Pinhole
+{
+    fn id(&self) -> &str { &self.id }
+    fn set_id(&mut self, id: String) { self.id = id; }
+}
+
+/// Router entries split by whether we created them
+struct Current<T>
+{
+    /// Keyed by managed id, which is also their id here so they compare equal to the wanted ones
+    managed: BTreeMap<String, T>,
+    /// Full router id of each managed entry, needed to delete it
+    full_ids: BTreeMap<String, String>,
+    others: Vec<T>,
+}
+
+impl<T: RouterEntry> Current<T>
+{
+    fn split(entries: Vec<T>) -> Current<T>
+    {
+        let mut current = Current { managed: BTreeMap::new(), full_ids: BTreeMap::new(), others: vec![] };
+
+        for mut entry in entries
+        {
+            match managed_key(entry.id())
+            {
+                Some(key) => {
+                    current.full_ids.insert(key.clone(), entry.id().to_string());
+                    entry.set_id(key.clone());
+                    current.managed.insert(key, entry);
+                }
+                None => current.others.push(entry),
+            }
+        }
+
+        current
+    }
+}
+
+/// One change bringing the router in line with the config
+pub enum Action<T>
+{
+    Create(T),
+    Update { current: T, wanted: T },
+    Delete(T),
+}
+
+impl<T: Display> Display for Action<T>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            Action::Create(wanted) => write!(f, "create {}", wanted),
+            Action::Update { current, wanted } => write!(f, "update {} (was {})", wanted, current),
+            Action::Delete(current) => write!(f, "delete {}", current),
+        }
+    }
+}
+
+/// Compares the managed router entries with the wanted ones, both keyed by their managed id
+fn diff<T: PartialEq>(mut current: BTreeMap<String, T>, wanted: BTreeMap<String, T>) -> Vec<Action<T>>
+{
+    let mut actions = vec![];
+
+    for (key, wanted) in wanted
+    {
+        match current.remove(&key)
+        {
+            None => actions.push(Action::Create(wanted)),
+            Some(current) if current != wanted => actions.push(Action::Update { current, wanted }),
+            Some(_) => {}
+        }
+    }
+
+    actions.extend(current.into_values().map(Action::Delete));
+
+    actions
+}
+
+/// Applies the actions with `apply`, or only reports them in dry-run mode; `label` names
+/// the kind of entry in the report, e.g. `Port forward`
+fn apply<T, F>(label: &str, actions: Vec<Action<T>>, dry_run: bool, report: &mut Report, mut apply: F)
+    where T: Display, F: FnMut(&Action<T>) -> Result<(), SahError>
+{
+    for action in actions
+    {
+        if dry_run
+        {
+            report.changes.push(format!("{} (dry run): would {}", label, action));
+            continue;
+        }
+
+        match apply(&action)
+        {
+            Ok(()) => report.changes.push(format!("{}: {}", label, action)),
+            Err(e) => report.errors.push(format!("{}: could not {}: {}", label, action, e)),
+        }
+    }
+}
+
+/// Outcome of one reconciliation, as lines for the log
+#[derive(Default)]
+pub struct Report
+{
+    /// Applied changes, or the changes that would be applied in dry-run mode
+    pub changes: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+/// Whether `[reconcile]` manages anything at all
+pub fn enabled(config: &Config) -> bool
+{
+    config.reconcile.port_forwards || config.reconcile.pinholes || config.reconcile.static_leases
+}
+
+/// Brings the router entries enabled in `[reconcile]` in line with the config;
+/// with `dry_run` the changes are only reported
+pub fn run(session: &mut Session, config: &Config, dry_run: bool) -> Report
+{
+    let mut report = Report::default();
+
+    // reservations first, the port forwards point at them
+    if config.reconcile.static_leases
+    {
+        static_lease::reconcile(session, &config.static_leases, dry_run, &mut report);
+    }
+    if config.reconcile.port_forwards
+    {
+        port_forward::reconcile(session, &config.port_forwards, dry_run, &mut report);
+    }
+    if config.reconcile.pinholes
+    {
+        pinhole::reconcile(session, &config.pinholes, dry_run, &mut report);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn managed_key_needs_the_exact_prefix()
+    {
+        assert_eq!(managed_key("webui_dynhost_https"), Some("dynhost_https".to_string()));
+        assert_eq!(managed_key("webui_old_dynhost_vpn"), None);
+        assert_eq!(managed_key("upnp_dynhost_https"), None);
+        assert_eq!(managed_key("dynhost_https"), None);
+        assert_eq!(managed_key("webuidynhost_https"), None);
+        assert_eq!(managed_key("webui_ssh"), None);
+    }
+
+    fn managed(ids: &[(&str, &str)]) -> BTreeMap<String, String>
+    {
+        ids.iter().filter_map(|(id, value)| Some((managed_key(id)?, value.to_string()))).collect()
+    }
+
+    #[test]
+    fn diff_leaves_unmanaged_entries_alone()
+    {
+        let current = managed(&[
+            ("webui_dynhost_web", "80"),
+            ("webui_dynhost_stale", "8080"),
+            ("webui_dynhost_same", "443"),
+            ("webui_old_dynhost_vpn", "1194"),
+        ]);
+        let wanted = BTreeMap::from([
+            ("dynhost_web".to_string(), "81".to_string()),
+            ("dynhost_same".to_string(), "443".to_string()),
+            ("dynhost_new".to_string(), "22".to_string()),
+        ]);
+
+        let actions: Vec<String> = diff(current, wanted).iter().map(|action| action.to_string()).collect();
+
+        assert_eq!(actions, ["create 22", "update 81 (was 80)", "delete 8080"]);
+    }
+}
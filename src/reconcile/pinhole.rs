@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+use std::net::Ipv6Addr;
+
+use crate::config::PinholeConfig;
+use crate::funbox::Session;
+use crate::funbox::firewall::Pinhole;
+use crate::util::with_interface_id;
+
+use super::{apply, diff, Action, Current, Report, MANAGED_PREFIX};
+
+fn wanted_pinhole(key: &str, pinhole: &PinholeConfig, prefix: Ipv6Addr) -> Pinhole
+{
+    Pinhole {
+        id: key.to_string(),
+        description: description(pinhole),
+        protocol: pinhole.protocol.code().to_string(),
+        port: pinhole.port.to_string(),
+        destination: with_interface_id(prefix, pinhole.interface_id).to_string(),
+        enabled: true,
+    }
+}
+
+fn description(pinhole: &PinholeConfig) -> String
+{
+    format!("dynhost {}", pinhole.name)
+}
+
+pub fn reconcile(session: &mut Session, pinholes: &[PinholeConfig], dry_run: bool, report: &mut Report)
+{
+    let prefix = match session.get_wan_status().map(|status| status.data.lan_prefix())
+    {
+        Ok(Some(prefix)) => prefix,
+        Ok(None) => {
+            report.errors.push("Pinholes: the router has no IPv6 prefix, pinholes left alone".to_string());
+            return;
+        }
+        Err(e) => {
+            report.errors.push(format!("Could not get the IPv6 prefix: {}", e));
+            return;
+        }
+    };
+
+    let current = match session.get_pinholes()
+    {
+        Ok(pinholes) => Current::split(pinholes),
+        Err(e) => {
+            report.errors.push(format!("Could not list pinholes: {}", e));
+            return;
+        }
+    };
+
+    let mut wanted = BTreeMap::new();
+    let mut configs = BTreeMap::new();
+
+    for pinhole in pinholes
+    {
+        let key = format!("{}{}", MANAGED_PREFIX, pinhole.name);
+        wanted.insert(key.clone(), wanted_pinhole(&key, pinhole, prefix));
+        configs.insert(key, pinhole);
+    }
+
+    // a new prefix shows up as an update of every pinhole, the old destinations are unreachable anyway
+    apply("Pinhole", diff(current.managed, wanted), dry_run, report, |action| match action
+    {
+        Action::Create(rule) | Action::Update { wanted: rule, .. } => {
+            let pinhole = configs[&rule.id];
+            session.set_pinhole(&rule.id, &description(pinhole), pinhole.protocol, pinhole.port,
+                with_interface_id(prefix, pinhole.interface_id)).map(|_| ())
+        }
+        Action::Delete(rule) => session.delete_pinhole(current.full_ids.get(&rule.id).unwrap_or(&rule.id)),
+    });
+}
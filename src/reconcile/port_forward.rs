@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use crate::config::PortForwardConfig;
+use crate::funbox::Session;
+use crate::funbox::firewall::PortForward;
+
+use super::{apply, diff, Action, Current, Report, MANAGED_PREFIX};
+
+fn wanted_rule(key: &str, forward: &PortForwardConfig) -> PortForward
+{
+    PortForward {
+        id: key.to_string(),
+        description: description(forward),
+        protocol: forward.protocol.code().to_string(),
+        external_port: forward.external_port.to_string(),
+        internal_port: forward.internal_port().to_string(),
+        destination: forward.internal_host.to_string(),
+        enabled: true,
+    }
+}
+
+fn description(forward: &PortForwardConfig) -> String
+{
+    format!("dynhost {}", forward.name)
+}
+
+/// Whether two protocol code lists (`6`, `17`, `6,17`) share a protocol
+fn protocols_overlap(a: &str, b: &str) -> bool
+{
+    a.split(',').any(|p| b.split(',').any(|q| p.trim() == q.trim()))
+}
+
+pub fn reconcile(session: &mut Session, forwards: &[PortForwardConfig], dry_run: bool, report: &mut Report)
+{
+    let current = match session.get_port_forwards()
+    {
+        Ok(rules) => Current::split(rules),
+        Err(e) => {
+            report.errors.push(format!("Could not list port forwards: {}", e));
+            return;
+        }
+    };
+
+    let mut wanted = BTreeMap::new();
+    let mut configs = BTreeMap::new();
+
+    for forward in forwards
+    {
+        let key = format!("{}{}", MANAGED_PREFIX, forward.name);
+        let rule = wanted_rule(&key, forward);
+
+        // the router refuses two rules on the same external port, ours would never be created
+        if let Some(other) = current.others.iter().find(|o| o.enabled && o.external_port == rule.external_port
+            && protocols_overlap(&o.protocol, &rule.protocol))
+        {
+            report.errors.push(format!("Port forward {}: external port {} is already used by {}, which is not managed by dynhost",
+                forward.name, rule.external_port, other));
+            continue;
+        }
+
+        wanted.insert(key.clone(), rule);
+        configs.insert(key, forward);
+    }
+
+    apply("Port forward", diff(current.managed, wanted), dry_run, report, |action| match action
+    {
+        Action::Create(rule) | Action::Update { wanted: rule, .. } => {
+            let forward = configs[&rule.id];
+            session.set_port_forward(&rule.id, &description(forward), forward.protocol, forward.external_port,
+                forward.internal_host, forward.internal_port()).map(|_| ())
+        }
+        Action::Delete(rule) => session.delete_port_forward(current.full_ids.get(&rule.id).unwrap_or(&rule.id)),
+    });
+}
@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::StaticLeaseConfig;
+use crate::funbox::Session;
+use crate::funbox::dhcp::StaticLease;
+
+use super::{apply, diff, Action, Report};
+
+/// Leases carry no id or description to mark them as ours, so only the MAC addresses of the
+/// config are managed: their leases are created and updated, never deleted
+pub fn reconcile(session: &mut Session, leases: &[StaticLeaseConfig], dry_run: bool, report: &mut Report)
+{
+    let router_leases = match session.get_static_leases()
+    {
+        Ok(leases) => leases,
+        Err(e) => {
+            report.errors.push(format!("Could not list static leases: {}", e));
+            return;
+        }
+    };
+
+    let config_macs: BTreeSet<String> = leases.iter().map(|lease| lease.mac()).collect();
+    let mut wanted = BTreeMap::new();
+    let mut configs = BTreeMap::new();
+
+    for lease in leases
+    {
+        let mac = lease.mac();
+
+        // the router refuses two leases for one address; a lease of another configured MAC is
+        // moved away below, e.g. when two devices swap their addresses
+        if let Some(other) = router_leases.iter().find(|l| l.ip == lease.ip.to_string() && !config_macs.contains(&l.mac))
+        {
+            report.errors.push(format!("Static lease {}: {} is already reserved for {}, which is not in the config",
+                lease.name, lease.ip, other.mac));
+            continue;
+        }
+
+        wanted.insert(mac.clone(), StaticLease { mac: mac.clone(), ip: lease.ip.to_string() });
+        configs.insert(mac, lease);
+    }
+
+    let current = router_leases.into_iter()
+        .filter(|l| configs.contains_key(&l.mac))
+        .map(|l| (l.mac.clone(), l))
+        .collect();
+
+    let actions = diff(current, wanted);
+
+    // there is no call to change a lease in place, and an address is only free once the lease
+    // holding it is gone: delete every lease that moves before adding any back
+    let mut deleted = BTreeSet::new();
+    if !dry_run
+    {
+        for action in &actions
+        {
+            if let Action::Update { current: lease, .. } = action
+            {
+                if session.delete_static_lease(&lease.mac).is_ok()
+                {
+                    deleted.insert(lease.mac.clone());
+                }
+            }
+        }
+    }
+
+    apply("Static lease", actions, dry_run, report, |action| match action
+    {
+        Action::Create(lease) => session.add_static_lease(&lease.mac, configs[&lease.mac].ip),
+        Action::Update { wanted: lease, .. } if deleted.contains(&lease.mac) => session.add_static_lease(&lease.mac, configs[&lease.mac].ip),
+        // the delete above failed, try again to report why
+        Action::Update { wanted: lease, .. } => session.delete_static_lease(&lease.mac)
+            .and_then(|_| session.add_static_lease(&lease.mac, configs[&lease.mac].ip)),
+        Action::Delete(lease) => session.delete_static_lease(&lease.mac),
+    });
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Whether retrying a failed update can succeed without a config change
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FailureKind
+{
+    Transient,
+    /// Worth retrying, but only after the longest delay and never before [`THROTTLED_MIN_DELAY`]
+    Throttled,
+    Permanent,
+}
+
+/// dyndns2 asks clients to wait at least 30 minutes after a `911` or `abuse`, whatever `update_retry_max` says
+pub const THROTTLED_MIN_DELAY: Duration = Duration::from_secs(30 * 60);
+
+struct RetryEntry
+{
+    failures: u32,
+    next_attempt: Instant,
+    kind: FailureKind,
+}
+
+impl RetryEntry
+{
+    fn is_blocked(&self, now: Instant) -> bool
+    {
+        match self.kind
+        {
+            FailureKind::Permanent => true,
+            FailureKind::Throttled => now < self.next_attempt,
+            FailureKind::Transient => false,
+        }
+    }
+}
+
+/// Tracks failed updates per key and tells when each one should be retried,
+/// using exponential backoff with jitter capped at `max_delay`
+pub struct RetryScheduler<K>
+{
+    base_delay: Duration,
+    max_delay: Duration,
+    entries: HashMap<K, RetryEntry>,
+}
+
+impl<K> RetryScheduler<K>
+    where K: Hash + Eq + Clone
+{
+    pub fn new(base_delay: Duration, max_delay: Duration) -> RetryScheduler<K>
+    {
+        RetryScheduler { base_delay, max_delay, entries: HashMap::new() }
+    }
+
+    pub fn record_success(&mut self, key: &K)
+    {
+        self.entries.remove(key);
+    }
+
+    /// Returns the delay until the next attempt, or `None` if the key is now disabled
+    pub fn record_failure(&mut self, key: &K, kind: FailureKind) -> Option<Duration>
+    {
+        let entry = self.entries.entry(key.clone()).or_insert(RetryEntry {
+            failures: 0,
+            next_attempt: Instant::now(),
+            kind,
+        });
+
+        entry.failures = entry.failures.saturating_add(1);
+        entry.kind = kind;
+
+        if kind == FailureKind::Permanent
+        {
+            return None;
+        }
+
+        let delay = match kind
+        {
+            FailureKind::Throttled => Self::backoff(self.max_delay, self.max_delay, 1).max(THROTTLED_MIN_DELAY),
+            _ => Self::backoff(self.base_delay, self.max_delay, entry.failures),
+        };
+        entry.next_attempt = Instant::now() + delay;
+
+        Some(delay)
+    }
+
+    /// Whether the key must not be updated now, even for a new address:
+    /// it is disabled or the provider asked us to wait
+    pub fn is_blocked(&self, key: &K) -> bool
+    {
+        self.entries.get(key).map(|e| e.is_blocked(Instant::now())).unwrap_or(false)
+    }
+
+    /// Keys whose retry time has come, disabled keys are never due
+    pub fn due(&self) -> Vec<K>
+    {
+        let now = Instant::now();
+
+        self.entries.iter()
+            .filter(|(_, entry)| entry.kind != FailureKind::Permanent && entry.next_attempt <= now)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// When the earliest pending retry is due, disabled keys are never due
+    pub fn next_due(&self) -> Option<Instant>
+    {
+        self.entries.values()
+            .filter(|entry| entry.kind != FailureKind::Permanent)
+            .map(|entry| entry.next_attempt)
+            .min()
+    }
+
+    /// Forgets pending retries of the keys matching `filter`, e.g. after the address changed
+    /// and they are updated anyway; blocked keys are kept
+    pub fn clear_pending<F>(&mut self, filter: F)
+        where F: Fn(&K) -> bool
+    {
+        let now = Instant::now();
+        self.entries.retain(|key, entry| !filter(key) || entry.is_blocked(now));
+    }
+
+    /// `base * 2^(failures-1)` capped at `max`, then randomized to between half and all of it
+    /// so that accounts failing together do not retry in lockstep
+    fn backoff(base: Duration, max: Duration, failures: u32) -> Duration
+    {
+        let exp = failures.saturating_sub(1).min(31);
+        let delay = base.saturating_mul(1u32 << exp).min(max);
+
+        let half = delay / 2;
+        half + Duration::from_millis(fastrand::u64(0..=half.as_millis() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    type Scheduler = RetryScheduler<&'static str>;
+
+    #[test]
+    fn backoff_doubles_within_jitter_bounds()
+    {
+        let base = Duration::from_secs(60);
+        let max = Duration::from_secs(3600);
+
+        for failures in 1..=6
+        {
+            let full = base * (1 << (failures - 1));
+            for _ in 0..100
+            {
+                let delay = Scheduler::backoff(base, max, failures);
+                assert!(delay >= full / 2 && delay <= full, "{} failures: {:?}", failures, delay);
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped()
+    {
+        let base = Duration::from_secs(60);
+        let max = Duration::from_secs(3600);
+
+        for failures in [7, 20, 32, u32::MAX]
+        {
+            let delay = Scheduler::backoff(base, max, failures);
+            assert!(delay >= max / 2 && delay <= max, "{} failures: {:?}", failures, delay);
+        }
+    }
+
+    #[test]
+    fn backoff_handles_zero_failures_and_base()
+    {
+        assert!(Scheduler::backoff(Duration::from_secs(60), Duration::from_secs(3600), 0) <= Duration::from_secs(60));
+        assert_eq!(Scheduler::backoff(Duration::ZERO, Duration::from_secs(3600), 5), Duration::ZERO);
+    }
+
+    #[test]
+    fn permanent_failures_are_never_due()
+    {
+        let mut retries = Scheduler::new(Duration::ZERO, Duration::ZERO);
+
+        assert_eq!(retries.record_failure(&"badauth", FailureKind::Permanent), None);
+        assert!(retries.record_failure(&"dnserr", FailureKind::Transient).is_some());
+
+        assert!(retries.is_blocked(&"badauth"));
+        assert_eq!(retries.due(), vec!["dnserr"]);
+        assert!(retries.next_due().is_some());
+
+        retries.record_success(&"dnserr");
+        assert_eq!(retries.next_due(), None);
+    }
+
+    #[test]
+    fn throttled_waits_at_least_the_minimum()
+    {
+        let mut retries = Scheduler::new(Duration::from_secs(10), Duration::from_secs(300));
+
+        for _ in 0..10
+        {
+            assert!(retries.record_failure(&"911", FailureKind::Throttled).unwrap() >= THROTTLED_MIN_DELAY);
+        }
+        assert!(retries.is_blocked(&"911"));
+        assert!(retries.due().is_empty());
+
+        let mut retries = Scheduler::new(Duration::from_secs(60), Duration::from_secs(7200));
+        let delay = retries.record_failure(&"abuse", FailureKind::Throttled).unwrap();
+        assert!(delay >= THROTTLED_MIN_DELAY && delay <= Duration::from_secs(7200));
+    }
+}
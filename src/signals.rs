@@ -0,0 +1,76 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::flag;
+
+/// How often an interruptible sleep checks for signals
+const SLEEP_STEP: Duration = Duration::from_millis(200);
+
+/// Flags set by SIGTERM/SIGINT (terminate) and SIGHUP (reload)
+pub struct Signals
+{
+    terminate: Arc<AtomicBool>,
+    reload: Arc<AtomicBool>,
+}
+
+impl Signals
+{
+    pub fn register() -> Result<Signals, String>
+    {
+        let terminate = Arc::new(AtomicBool::new(false));
+        let reload = Arc::new(AtomicBool::new(false));
+
+        for signal in [SIGTERM, SIGINT]
+        {
+            // a second signal while shutting down kills the process right away
+            flag::register_conditional_shutdown(signal, 1, Arc::clone(&terminate)).map_err(|e| e.to_string())?;
+            flag::register(signal, Arc::clone(&terminate)).map_err(|e| e.to_string())?;
+        }
+
+        flag::register(SIGHUP, Arc::clone(&reload)).map_err(|e| e.to_string())?;
+
+        Ok(Signals { terminate, reload })
+    }
+
+    pub fn should_terminate(&self) -> bool
+    {
+        self.terminate.load(Ordering::Relaxed)
+    }
+
+    /// Whether a signal arrived that the main loop has to act on
+    pub fn interrupted(&self) -> bool
+    {
+        self.should_terminate() || self.reload.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether a reload was requested since the last call
+    pub fn take_reload(&self) -> bool
+    {
+        self.reload.swap(false, Ordering::Relaxed)
+    }
+
+    /// Sleeps for `duration`, returning early when a signal arrives
+    pub fn sleep(&self, duration: Duration)
+    {
+        let end = Instant::now() + duration;
+
+        loop
+        {
+            if self.interrupted()
+            {
+                return;
+            }
+
+            let now = Instant::now();
+            if now >= end
+            {
+                return;
+            }
+
+            sleep(SLEEP_STEP.min(end - now));
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+
+use crate::funbox::Session;
+
+use super::{ChangeWait, Detection, IpSource, SourceError};
+
+/// Event handlers reporting WAN address and link changes
+const WAN_EVENT_HANDLERS: [&str; 2] = ["NMC", "NeMo.Intf.data"];
+
+impl IpSource for Session
+{
+    fn name(&self) -> String
+    {
+        format!("funbox {}", self.address())
+    }
+
+    fn detect(&mut self) -> Result<Detection, SourceError>
+    {
+        let status = self.get_wan_status()?;
+        let wan = status.data;
+
+        let mut addresses = vec![];
+
+        match wan.IPAddress
+        {
+            Some(ip) if ip.is_ipv4() => addresses.push(ip),
+            Some(ip) => return Err(SourceError::InvalidAddress(ip.to_string())),
+            None => {}
+        }
+
+        // IPv6 is optional, the line may not have it enabled
+        if let Some(v6) = wan.IPv6Address.filter(|ip| ip.is_ipv6())
+        {
+            addresses.push(v6);
+        }
+
+        if addresses.is_empty()
+        {
+            return Err(SourceError::NoAddress);
+        }
+
+        Ok(Detection {
+            addresses,
+            source: self.name(),
+            details: Some(format!("{} {}", wan.LinkType, wan.LinkState)),
+            lan_prefix: wan.lan_prefix(),
+            link: Some(wan.LinkType),
+            withheld: vec![],
+        })
+    }
+
+    fn connectivity(&mut self) -> Result<Option<bool>, SourceError>
+    {
+        Ok(Some(self.is_internet()?))
+    }
+
+    fn router(&mut self) -> Option<&mut Session>
+    {
+        Some(self)
+    }
+
+    fn wait_for_change(&mut self, timeout: Duration) -> Result<ChangeWait, SourceError>
+    {
+        // every event on these handlers is worth one getWANStatus call, which is far
+        // cheaper than polling it all the time
+        if self.wait_events(&WAN_EVENT_HANDLERS, timeout)?.is_empty()
+        {
+            Ok(ChangeWait::Timeout)
+        }
+        else
+        {
+            Ok(ChangeWait::Changed)
+        }
+    }
+}
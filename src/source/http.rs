@@ -0,0 +1,143 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use reqwest::blocking::Client;
+
+use crate::util::parse_ip;
+
+use super::{Detection, IpSource, SourceError};
+
+/// Failure of one "what is my IP" web service
+#[derive(Debug)]
+pub enum FetchError
+{
+    Transport { url: String, source: reqwest::Error },
+    HttpStatus { url: String, status: u16 },
+    /// The body is not an address
+    InvalidAddress { url: String, text: String },
+    /// The service answered with an address of the other family
+    WrongFamily { url: String, addr: IpAddr },
+}
+
+impl std::fmt::Display for FetchError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            FetchError::Transport { url, source } => write!(f, "Could not reach {}: {}", url, source),
+            FetchError::HttpStatus { url, status } => write!(f, "{} answered with HTTP status {}", url, status),
+            FetchError::InvalidAddress { url, text } => write!(f, "{} returned wrong public IP '{}'", url, text),
+            FetchError::WrongFamily { url, addr } => {
+                write!(f, "{} returned {} instead of an {} address", url, addr, if addr.is_ipv4() { "IPv6" } else { "IPv4" })
+            }
+        }
+    }
+}
+
+impl std::error::Error for FetchError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            FetchError::Transport { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Asks a "what is my IP" web service, for sites without a FunBox
+pub struct HttpIpSource
+{
+    ipv4_client: Client,
+    ipv6_client: Client,
+    ipv4_url: Option<String>,
+    ipv6_url: Option<String>,
+}
+
+impl HttpIpSource
+{
+    pub fn new(ipv4_url: Option<String>, ipv6_url: Option<String>) -> Result<HttpIpSource, reqwest::Error>
+    {
+        // binding to the unspecified address forces the address family of the connection,
+        // so a dual-stack service answers with the address we ask for
+        let build = |local: IpAddr| Client::builder()
+            .gzip(true)
+            .use_native_tls()
+            .local_address(local)
+            .build();
+
+        Ok(HttpIpSource {
+            ipv4_client: build(IpAddr::V4(Ipv4Addr::UNSPECIFIED))?,
+            ipv6_client: build(IpAddr::V6(Ipv6Addr::UNSPECIFIED))?,
+            ipv4_url,
+            ipv6_url,
+        })
+    }
+
+    fn fetch(client: &Client, url: &str) -> Result<IpAddr, FetchError>
+    {
+        let transport = |source| FetchError::Transport { url: url.to_string(), source };
+
+        let response = client.get(url).send().map_err(transport)?;
+
+        if !response.status().is_success()
+        {
+            return Err(FetchError::HttpStatus { url: url.to_string(), status: response.status().as_u16() });
+        }
+
+        let text = response.text().map_err(transport)?;
+
+        parse_ip(&text).ok_or_else(|| FetchError::InvalidAddress { url: url.to_string(), text: text.trim().to_string() })
+    }
+}
+
+impl IpSource for HttpIpSource
+{
+    fn name(&self) -> String
+    {
+        let urls: Vec<&str> = self.ipv4_url.iter().chain(self.ipv6_url.iter()).map(|u| u.as_str()).collect();
+        format!("http {}", urls.join(", "))
+    }
+
+    fn detect(&mut self) -> Result<Detection, SourceError>
+    {
+        let mut addresses = vec![];
+        let mut errors = vec![];
+
+        if let Some(url) = &self.ipv4_url
+        {
+            match Self::fetch(&self.ipv4_client, url)
+            {
+                Ok(addr) if addr.is_ipv4() => addresses.push(addr),
+                Ok(addr) => errors.push(FetchError::WrongFamily { url: url.clone(), addr }),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if let Some(url) = &self.ipv6_url
+        {
+            match Self::fetch(&self.ipv6_client, url)
+            {
+                Ok(addr) if addr.is_ipv6() => addresses.push(addr),
+                Ok(addr) => errors.push(FetchError::WrongFamily { url: url.clone(), addr }),
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if addresses.is_empty()
+        {
+            return Err(SourceError::Http(errors));
+        }
+
+        // a single failing address family is reported but does not fail the whole detection
+        Ok(Detection {
+            addresses,
+            source: self.name(),
+            details: if errors.is_empty() { None } else { Some(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; ")) },
+            link: None,
+            lan_prefix: None,
+            withheld: vec![],
+        })
+    }
+}
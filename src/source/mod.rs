@@ -0,0 +1,154 @@
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
+
+use crate::config::{Config, RecordType, SourceConfig};
+use crate::funbox::{SahError, Session};
+use crate::funbox::packets::LinkType;
+
+use self::http::{FetchError, HttpIpSource};
+
+mod funbox;
+mod http;
+
+/// Public addresses reported by an [`IpSource`]
+pub struct Detection
+{
+    pub addresses: Vec<IpAddr>,
+    /// Human readable name of the source, e.g. `funbox 192.168.1.1`
+    pub source: String,
+    /// Source specific details, e.g. the WAN link type reported by the router
+    pub details: Option<String>,
+    /// Uplink reported by the router, `None` if the source cannot tell
+    pub link: Option<LinkType>,
+    /// The /64 the LAN addresses come from, see [`WanStatus::lan_prefix`](crate::funbox::packets::WanStatus::lan_prefix); `None` if the source cannot tell
+    pub lan_prefix: Option<Ipv6Addr>,
+    /// Addresses the `[policy]` dropped without a replacement, see [`policy::apply`](crate::policy::apply)
+    pub withheld: Vec<IpAddr>,
+}
+
+impl Detection
+{
+    /// First reported address that fits the record type
+    pub fn address_for(&self, record: RecordType) -> Option<IpAddr>
+    {
+        self.addresses.iter().copied().find(|addr| record.matches(addr))
+    }
+
+    /// Whether the source reported an address for the record type but the policy did not publish it
+    pub fn is_withheld(&self, record: RecordType) -> bool
+    {
+        self.address_for(record).is_none() && self.withheld.iter().any(|addr| record.matches(addr))
+    }
+}
+
+#[derive(Debug)]
+pub enum SourceError
+{
+    /// The router could not be queried
+    Router(SahError),
+    /// None of the web services gave a usable answer
+    Http(Vec<FetchError>),
+    /// The source answered with something that is not a usable public address
+    InvalidAddress(String),
+    /// The source answered without any address, e.g. while the WAN is connecting
+    NoAddress,
+}
+
+impl std::fmt::Display for SourceError
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            SourceError::Router(e) => write!(f, "{}", e),
+            SourceError::Http(errors) => {
+                write!(f, "{}", errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("; "))
+            }
+            SourceError::InvalidAddress(addr) => write!(f, "Got wrong public IP '{}'", addr),
+            SourceError::NoAddress => write!(f, "No WAN address reported"),
+        }
+    }
+}
+
+impl std::error::Error for SourceError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+        match self
+        {
+            SourceError::Router(e) => Some(e),
+            SourceError::Http(errors) => errors.first().map(|e| e as &(dyn std::error::Error + 'static)),
+            SourceError::InvalidAddress(_) | SourceError::NoAddress => None,
+        }
+    }
+}
+
+impl From<SahError> for SourceError
+{
+    fn from(e: SahError) -> SourceError
+    {
+        SourceError::Router(e)
+    }
+}
+
+/// Result of waiting for the source to report an address change
+pub enum ChangeWait
+{
+    /// Something changed, detect again
+    Changed,
+    /// Nothing happened within the timeout
+    Timeout,
+    /// The source cannot push changes and has to be polled
+    Unsupported,
+}
+
+/// Something able to tell the current public addresses of this network
+pub trait IpSource
+{
+    fn name(&self) -> String;
+
+    fn detect(&mut self) -> Result<Detection, SourceError>;
+
+    /// Whether the WAN is up, `None` if the source cannot tell
+    fn connectivity(&mut self) -> Result<Option<bool>, SourceError>
+    {
+        Ok(None)
+    }
+
+    /// The router session behind the source, so other router work shares its login
+    fn router(&mut self) -> Option<&mut Session>
+    {
+        None
+    }
+
+    /// Blocks up to `timeout` until the source reports that the addresses may have changed
+    fn wait_for_change(&mut self, _timeout: Duration) -> Result<ChangeWait, SourceError>
+    {
+        Ok(ChangeWait::Unsupported)
+    }
+}
+
+/// Creates the source selected by the `[source]` config section
+pub fn create(config: &Config) -> Result<Box<dyn IpSource>, String>
+{
+    match &config.source
+    {
+        SourceConfig::Funbox => {
+            let router = config.router.as_ref()
+                .ok_or_else(|| "The funbox source requires a [router] section".to_string())?;
+
+            // logs in on the first detection, so a router that is down at startup is retried
+            let session = Session::new(&router.address, &router.username, &router.password, router.session_cache())
+                .map_err(|e| format!("Could not create FunBox session: {}", e))?;
+
+            Ok(Box::new(session))
+        }
+        SourceConfig::Http { .. } => {
+            let (ipv4_url, ipv6_url) = config.source.http_urls();
+            let source = HttpIpSource::new(ipv4_url, ipv6_url)
+                .map_err(|e| format!("Could not create HTTP client: {}", e))?;
+
+            Ok(Box::new(source))
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+
+use crate::config::RecordType;
+
+/// What the daemon knows about one published record
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RecordState
+{
+    /// Last address the provider accepted
+    pub last_ip: Option<IpAddr>,
+    /// When `last_ip` was accepted
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Last answer of the provider, successful or not
+    pub last_response: Option<String>,
+    /// Consecutive failed updates
+    pub failures: u32,
+}
+
+/// Time span during which the WAN was reported down
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Outage
+{
+    pub started_at: DateTime<Utc>,
+    /// `None` while the outage goes on
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+impl Outage
+{
+    pub fn duration(&self) -> chrono::Duration
+    {
+        self.ended_at.unwrap_or_else(Utc::now) - self.started_at
+    }
+}
+
+/// Oldest outages are dropped beyond this many
+const MAX_OUTAGES: usize = 500;
+
+/// Router found by `address = "auto"`, reused as long as it answers
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DiscoveredRouter
+{
+    pub address: String,
+    /// `default gateway` or `SSDP`
+    pub method: String,
+    pub discovered_at: DateTime<Utc>,
+}
+
+/// A LAN device seen by the device snapshots
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SeenDevice
+{
+    pub name: String,
+    pub ip: String,
+    pub active: bool,
+    pub first_seen: DateTime<Utc>,
+    /// Last snapshot it was connected in
+    pub last_seen: DateTime<Utc>,
+}
+
+/// When the running daemon looks at the source next, for the status output; kept in a small
+/// file next to the state file since it changes on every poll
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PollState
+{
+    /// `polling` or `events`
+    pub mode: String,
+    pub interval_secs: u64,
+    pub next_poll: DateTime<Utc>,
+}
+
+impl PollState
+{
+    /// `<state file>.poll`
+    pub fn path(state_file: &Path) -> PathBuf
+    {
+        let mut path = PathBuf::from(state_file);
+        path.as_mut_os_string().push(".poll");
+        path
+    }
+
+    /// `None` if the file is missing or unreadable, e.g. while the daemon rewrites it
+    pub fn load(state_file: &Path) -> Option<PollState>
+    {
+        fs::read(Self::path(state_file)).ok().and_then(|bytes| serde_json::from_slice(&bytes).ok())
+    }
+
+    /// Plain overwrite without fsync, losing it in a crash costs nothing; `None` removes the file
+    pub fn save(poll: Option<&PollState>, state_file: &Path) -> Result<(), String>
+    {
+        let path = Self::path(state_file);
+
+        match poll
+        {
+            Some(poll) => {
+                if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty())
+                {
+                    fs::create_dir_all(dir).map_err(|e| format!("Could not create '{}': {}", dir.display(), e))?;
+                }
+
+                let json = serde_json::to_vec(poll).map_err(|e| e.to_string())?;
+                fs::write(&path, json).map_err(|e| format!("Could not write '{}': {}", path.display(), e))
+            }
+            None => match fs::remove_file(&path)
+            {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Could not remove '{}': {}", path.display(), e)),
+                _ => Ok(()),
+            },
+        }
+    }
+}
+
+/// Persistent daemon state, so restarts only update records that actually differ
+#[derive(Serialize, Deserialize, Default)]
+pub struct State
+{
+    /// Keyed by `<domain>/<record type>`
+    #[serde(default)]
+    pub records: BTreeMap<String, RecordState>,
+    #[serde(default)]
+    pub outages: Vec<Outage>,
+    #[serde(default)]
+    pub router: Option<DiscoveredRouter>,
+    /// Keyed by MAC address
+    #[serde(default)]
+    pub devices: BTreeMap<String, SeenDevice>,
+}
+
+impl State
+{
+    pub fn key(domain: &str, record: RecordType) -> String
+    {
+        format!("{}/{}", domain, record)
+    }
+
+    /// Loads the state, a missing file gives an empty state
+    pub fn load(path: &Path) -> Result<State, String>
+    {
+        match fs::read(path)
+        {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| format!("Could not parse state file '{}': {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(format!("Could not read state file '{}': {}", path.display(), e)),
+        }
+    }
+
+    /// Writes the state to a temporary file next to `path` and renames it over `path`,
+    /// so a crash never leaves a truncated state behind
+    pub fn save(&self, path: &Path) -> Result<(), String>
+    {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir).map_err(|e| format!("Could not create '{}': {}", dir.display(), e))?;
+        }
+
+        let mut tmp_path = PathBuf::from(path);
+        tmp_path.as_mut_os_string().push(".tmp");
+
+        let json = serde_json::to_vec_pretty(self).map_err(|e| e.to_string())?;
+
+        let mut file = File::create(&tmp_path).map_err(|e| format!("Could not create '{}': {}", tmp_path.display(), e))?;
+        file.write_all(&json)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| format!("Could not write '{}': {}", tmp_path.display(), e))?;
+
+        fs::rename(&tmp_path, path).map_err(|e| format!("Could not replace '{}': {}", path.display(), e))
+    }
+
+    pub fn record(&self, domain: &str, record: RecordType) -> Option<&RecordState>
+    {
+        self.records.get(&Self::key(domain, record))
+    }
+
+    /// Whether `ip` is what the provider last accepted for the record
+    pub fn is_published(&self, domain: &str, record: RecordType, ip: IpAddr) -> bool
+    {
+        self.record(domain, record).and_then(|r| r.last_ip) == Some(ip)
+    }
+
+    pub fn record_success(&mut self, domain: &str, record: RecordType, ip: IpAddr, response: String)
+    {
+        let entry = self.records.entry(Self::key(domain, record)).or_default();
+        entry.last_ip = Some(ip);
+        entry.updated_at = Some(Utc::now());
+        entry.last_response = Some(response);
+        entry.failures = 0;
+    }
+
+    pub fn record_failure(&mut self, domain: &str, record: RecordType, response: String)
+    {
+        let entry = self.records.entry(Self::key(domain, record)).or_default();
+        entry.last_response = Some(response);
+        entry.failures = entry.failures.saturating_add(1);
+    }
+
+    pub fn current_outage(&self) -> Option<&Outage>
+    {
+        self.outages.last().filter(|o| o.ended_at.is_none())
+    }
+
+    /// Opens an outage unless one is already going on, returns whether a new one was opened
+    pub fn start_outage(&mut self) -> bool
+    {
+        if self.current_outage().is_some()
+        {
+            return false;
+        }
+
+        self.outages.push(Outage { started_at: Utc::now(), ended_at: None });
+
+        if self.outages.len() > MAX_OUTAGES
+        {
+            let excess = self.outages.len() - MAX_OUTAGES;
+            self.outages.drain(..excess);
+        }
+
+        true
+    }
+
+    /// Closes the ongoing outage and returns it
+    pub fn end_outage(&mut self) -> Option<Outage>
+    {
+        let outage = self.outages.last_mut().filter(|o| o.ended_at.is_none())?;
+        outage.ended_at = Some(Utc::now());
+
+        Some(outage.clone())
+    }
+}
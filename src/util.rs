@@ -1,64 +1,269 @@
-pub fn is_ipv4(s: &String) -> bool
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Parses an address as reported by routers and web services: surrounding whitespace,
+/// `[...]` brackets and an IPv6 zone (`%eth0`) are accepted, then the address is normalised.
+/// Octets with leading zeros are rejected, they are read as octal by some parsers
+pub fn parse_ip(s: &str) -> Option<IpAddr>
+{
+    let s = s.trim();
+    let s = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(s);
+    let s = s.split('%').next().unwrap_or(s);
+
+    s.parse::<IpAddr>().ok().map(normalise)
+}
+
+/// Turns IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) into the IPv4 address they carry,
+/// so they are published as A records and compared equal to the plain IPv4 form
+pub fn normalise(ip: IpAddr) -> IpAddr
+{
+    ip.to_canonical()
+}
+
+/// Parses a prefix in CIDR notation, e.g. `2a01:cb00:1:200::/56`
+pub fn parse_ipv6_prefix(s: &str) -> Option<(Ipv6Addr, u8)>
 {
-    //217.97.122.207
-    if s.len() < 7 || s.len() > 15
+    let (addr, len) = s.trim().split_once('/')?;
+    let len = len.parse::<u8>().ok().filter(|len| *len <= 128)?;
+
+    Some((addr.parse().ok()?, len))
+}
+
+/// The LAN address of a host: the first /64 of `prefix` followed by the host `interface_id`
+pub fn with_interface_id(prefix: Ipv6Addr, interface_id: Ipv6Addr) -> Ipv6Addr
+{
+    const HOST_MASK: u128 = u64::MAX as u128;
+
+    Ipv6Addr::from((u128::from(prefix) & !HOST_MASK) | (u128::from(interface_id) & HOST_MASK))
+}
+
+/// Why an address can or cannot be reached from the internet
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AddressClass
+{
+    /// Globally routable
+    Global,
+    /// RFC 1918 `10/8`, `172.16/12`, `192.168/16`
+    Private,
+    /// RFC 6598 `100.64/10`, carrier-grade NAT
+    Shared,
+    Loopback,
+    /// `169.254/16`, `fe80::/10`
+    LinkLocal,
+    /// IPv6 unique local `fc00::/7`, the IPv6 counterpart of RFC 1918
+    UniqueLocal,
+    /// `::ffff:a.b.c.d`, an IPv4 address in IPv6 clothing, see [`normalise`]
+    V4Mapped,
+    /// `192.0.2/24`, `198.51.100/24`, `203.0.113/24`, `2001:db8::/32`
+    Documentation,
+    Unspecified,
+    Multicast,
+    /// Any other reserved range: `0/8`, `192.0.0/24`, `198.18/15` benchmarking, `240/4`, broadcast
+    Reserved,
+}
+
+impl AddressClass
+{
+    pub fn is_global(&self) -> bool
     {
-        return false;
+        *self == AddressClass::Global
     }
 
-    fn check_octet(octet: &String) -> bool
+    pub fn description(&self) -> &'static str
     {
-        match octet.len()
+        match self
         {
-            3 => {
-                let mut chrs = octet.chars();
-                let ch1 = chrs.next().unwrap();
-
-                if !(ch1 == '1' || ch1 == '2') { return false; }
-                if ch1 == '2'
-                {
-                    let ch2 = chrs.next().unwrap();
-                    if !(ch2 == '0' || ch2 == '1' || ch2 == '2' || ch2 == '3' || ch2 == '4')
-                    {
-                        if ch2 == '5'
-                        {
-                            let ch3 = chrs.next().unwrap();
-                            if !(ch3 == '0' || ch3 == '1' || ch3 == '2' || ch3 == '3' || ch3 == '4' || ch3 == '5')
-                            {
-                                return false;
-                            }
-                        }
-                        else
-                        {
-                            return false;
-                        }
-                    }
-                }
-            },
-            2 => {},
-            1 => {},
-            _ => return false
+            AddressClass::Global => "public address",
+            AddressClass::Private => "private RFC 1918 address",
+            AddressClass::Shared => "carrier-grade NAT address in 100.64.0.0/10",
+            AddressClass::Loopback => "loopback address",
+            AddressClass::LinkLocal => "link-local address",
+            AddressClass::UniqueLocal => "unique local IPv6 address",
+            AddressClass::V4Mapped => "IPv4-mapped IPv6 address",
+            AddressClass::Documentation => "documentation address",
+            AddressClass::Unspecified => "unspecified address",
+            AddressClass::Multicast => "multicast address",
+            AddressClass::Reserved => "reserved address",
         }
+    }
+}
 
-        true
+pub fn classify(ip: &IpAddr) -> AddressClass
+{
+    match ip
+    {
+        IpAddr::V4(v4) => classify_v4(v4),
+        IpAddr::V6(v6) => classify_v6(v6),
     }
+}
+
+fn classify_v4(ip: &Ipv4Addr) -> AddressClass
+{
+    let [a, b, c, _] = ip.octets();
 
-    let mut octet = String::new();
-    for ch in s.chars()
+    if ip.is_unspecified()
     {
-        if ch == '.'
-        {
-            if !check_octet(&octet) { return false; }
+        AddressClass::Unspecified
+    }
+    else if ip.is_loopback()
+    {
+        AddressClass::Loopback
+    }
+    else if ip.is_private()
+    {
+        AddressClass::Private
+    }
+    else if a == 100 && (64..128).contains(&b)
+    {
+        AddressClass::Shared
+    }
+    else if ip.is_link_local()
+    {
+        AddressClass::LinkLocal
+    }
+    else if ip.is_documentation()
+    {
+        AddressClass::Documentation
+    }
+    else if ip.is_multicast()
+    {
+        AddressClass::Multicast
+    }
+    else if a == 0 || a >= 240 || (a == 192 && b == 0 && c == 0) || (a == 198 && (b == 18 || b == 19))
+    {
+        AddressClass::Reserved
+    }
+    else
+    {
+        AddressClass::Global
+    }
+}
 
-            octet = String::new();
-        }
-        else
-        {
-            octet.push(ch);
-        }
+fn classify_v6(ip: &Ipv6Addr) -> AddressClass
+{
+    let segments = ip.segments();
+
+    if ip.is_unspecified()
+    {
+        AddressClass::Unspecified
+    }
+    else if ip.is_loopback()
+    {
+        AddressClass::Loopback
+    }
+    else if ip.to_ipv4_mapped().is_some()
+    {
+        AddressClass::V4Mapped
+    }
+    else if segments[0] & 0xffc0 == 0xfe80
+    {
+        AddressClass::LinkLocal
+    }
+    else if segments[0] & 0xfe00 == 0xfc00
+    {
+        AddressClass::UniqueLocal
+    }
+    else if segments[0] == 0x2001 && segments[1] == 0x0db8
+    {
+        AddressClass::Documentation
     }
+    else if ip.is_multicast()
+    {
+        AddressClass::Multicast
+    }
+    // only 2000::/3 is handed out as global unicast
+    else if segments[0] & 0xe000 != 0x2000
+    {
+        AddressClass::Reserved
+    }
+    else
+    {
+        AddressClass::Global
+    }
+}
 
-    if !check_octet(&octet) { return false; }
+/// Formats a duration as e.g. `1h 02m 03s`, `5m 12s` or `42s`
+pub fn format_duration(duration: chrono::Duration) -> String
+{
+    let secs = duration.num_seconds().max(0);
+    let (h, m, s) = (secs / 3600, secs % 3600 / 60, secs % 60);
 
-    true
-}
\ No newline at end of file
+    if h > 0
+    {
+        format!("{}h {:02}m {:02}s", h, m, s)
+    }
+    else if m > 0
+    {
+        format!("{}m {:02}s", m, s)
+    }
+    else
+    {
+        format!("{}s", s)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn class(s: &str) -> AddressClass
+    {
+        classify(&s.parse().unwrap())
+    }
+
+    #[test]
+    fn parse_rejects_invalid_addresses()
+    {
+        assert_eq!(parse_ip("999.1.1.1"), None);
+        assert_eq!(parse_ip("256.0.0.1"), None);
+        assert_eq!(parse_ip("1.2.3"), None);
+        assert_eq!(parse_ip(""), None);
+        assert_eq!(parse_ip("example.com"), None);
+        // would be octal for inet_aton
+        assert_eq!(parse_ip("010.1.1.1"), None);
+        assert_eq!(parse_ip("1.2.3.04"), None);
+    }
+
+    #[test]
+    fn parse_accepts_router_and_service_formats()
+    {
+        assert_eq!(parse_ip(" 90.1.2.3\n"), Some("90.1.2.3".parse().unwrap()));
+        assert_eq!(parse_ip("[2a01:cb00::1]"), Some("2a01:cb00::1".parse().unwrap()));
+        assert_eq!(parse_ip("fe80::1%eth0"), Some("fe80::1".parse().unwrap()));
+        assert_eq!(parse_ip("::ffff:90.1.2.3"), Some("90.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn classifies_ipv4()
+    {
+        assert_eq!(class("90.1.2.3"), AddressClass::Global);
+        assert_eq!(class("192.168.1.1"), AddressClass::Private);
+        assert_eq!(class("172.16.0.1"), AddressClass::Private);
+        assert_eq!(class("172.32.0.1"), AddressClass::Global);
+        assert_eq!(class("100.64.0.1"), AddressClass::Shared);
+        assert_eq!(class("100.127.255.255"), AddressClass::Shared);
+        assert_eq!(class("100.128.0.1"), AddressClass::Global);
+        assert_eq!(class("127.0.0.1"), AddressClass::Loopback);
+        assert_eq!(class("169.254.1.1"), AddressClass::LinkLocal);
+        assert_eq!(class("203.0.113.5"), AddressClass::Documentation);
+        assert_eq!(class("0.0.0.0"), AddressClass::Unspecified);
+        assert_eq!(class("224.0.0.1"), AddressClass::Multicast);
+        assert_eq!(class("198.18.0.1"), AddressClass::Reserved);
+        assert_eq!(class("255.255.255.255"), AddressClass::Reserved);
+    }
+
+    #[test]
+    fn classifies_ipv6()
+    {
+        assert_eq!(class("2a01:cb00:1:2::1"), AddressClass::Global);
+        assert_eq!(class("fd12:3456::1"), AddressClass::UniqueLocal);
+        assert_eq!(class("fc00::1"), AddressClass::UniqueLocal);
+        assert_eq!(class("::ffff:90.1.2.3"), AddressClass::V4Mapped);
+        assert_eq!(class("fe80::1"), AddressClass::LinkLocal);
+        assert_eq!(class("::1"), AddressClass::Loopback);
+        assert_eq!(class("::"), AddressClass::Unspecified);
+        assert_eq!(class("2001:db8::1"), AddressClass::Documentation);
+        assert_eq!(class("ff02::1"), AddressClass::Multicast);
+        assert_eq!(class("4000::1"), AddressClass::Reserved);
+    }
+}